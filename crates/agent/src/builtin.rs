@@ -0,0 +1,52 @@
+//! Tools every agent can have.
+use super::tool::Tools;
+use anyhow::{Result, bail};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Now {}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Calc {
+    pub op: Op,
+    pub a: f64,
+    pub b: f64,
+}
+
+fn calc(x: Calc) -> Result<Value> {
+    let r = match x.op {
+        Op::Add => x.a + x.b,
+        Op::Sub => x.a - x.b,
+        Op::Mul => x.a * x.b,
+        Op::Div if x.b == 0.0 => bail!("division by zero"),
+        Op::Div => x.a / x.b,
+    };
+    Ok(json!(r))
+}
+
+/// `now` and `calc`.
+pub fn builtin() -> Tools {
+    let mut tools = Tools::default();
+    tools
+        .register("now", "The current time, RFC 3339 in UTC", |_: Now| async {
+            Ok(json!(Utc::now().to_rfc3339()))
+        })
+        .register(
+            "calc",
+            "Adds, subtracts, multiplies or divides `a` by `b`",
+            |x: Calc| async { calc(x) },
+        );
+    tools
+}
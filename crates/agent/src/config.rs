@@ -0,0 +1,91 @@
+use super::model::Model;
+use figment::{
+    Figment, Result,
+    providers::{Env, Format, Toml},
+};
+use message::config::Queue;
+use message::trace::Otlp;
+use serde::Deserialize;
+use serde_with::{OneOrMany, serde_as};
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub enum LogFormat {
+    #[allow(non_camel_case_types)]
+    json,
+    #[default]
+    #[allow(non_camel_case_types)]
+    compact,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Log {
+    #[serde(default)]
+    pub format: LogFormat,
+    pub otlp: Option<Otlp>,
+}
+
+fn default_event() -> Vec<String> {
+    vec!["agent".to_owned()]
+}
+
+fn default_reply() -> String {
+    "chat".to_owned()
+}
+
+fn default_max_steps() -> usize {
+    8
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct AgentConf {
+    /// Globs of the events asked, one or a list.
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default = "default_event")]
+    pub event: Vec<String>,
+    /// Event the steps and the answer join.
+    #[serde(default = "default_reply")]
+    pub reply: String,
+    pub system: Option<String>,
+    #[serde(default = "default_max_steps")]
+    pub max_steps: usize,
+    /// Runs at once.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+impl Default for AgentConf {
+    fn default() -> Self {
+        Self {
+            event: default_event(),
+            reply: default_reply(),
+            system: None,
+            max_steps: default_max_steps(),
+            concurrency: default_concurrency(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    pub queue: Queue,
+    #[serde(default)]
+    pub trace: Log,
+    pub model: Model,
+    #[serde(default)]
+    pub agent: AgentConf,
+}
+
+impl Config {
+    #[allow(clippy::result_large_err)]
+    pub fn new() -> Result<Self> {
+        Figment::new()
+            .merge(Toml::file("agent.toml"))
+            .merge(Env::prefixed("AGENT_").split("_"))
+            .extract()
+    }
+}
@@ -0,0 +1,13 @@
+//! A tool-calling agent over an OpenAI-compatible model. Every step of a run
+//! goes out to the asking session as a brick, the reasoning folded and the
+//! tool results as tables, before the answer itself.
+pub mod builtin;
+pub mod config;
+pub mod model;
+pub mod runtime;
+pub mod step;
+pub mod tool;
+
+pub use model::{Model, Turn};
+pub use runtime::{Agent, Step};
+pub use tool::Tools;
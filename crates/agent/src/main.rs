@@ -1,3 +1,110 @@
-fn main() {
-    println!("Hello, world!");
+use agent::Agent;
+use agent::builtin::builtin;
+use agent::config::{Config, LogFormat};
+use anyhow::{Result, bail};
+use message::queue::MessageQueue;
+use message::server::shutdown_signal;
+use message::time::Created;
+use message::{ChatMessage, Envelope, Event, glob};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{Instrument, debug, error, info, info_span};
+use tracing_subscriber::{
+    EnvFilter, fmt::layer, prelude::__tracing_subscriber_SubscriberExt, registry,
+    util::SubscriberInitExt,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cfg = Config::new()?;
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (otel, provider) = match &cfg.trace.otlp {
+        Some(otlp) => Some(message::trace::layer(otlp, "agent")?),
+        None => None,
+    }
+    .unzip();
+    match &cfg.trace.format {
+        LogFormat::compact => {
+            registry()
+                .with(otel)
+                .with(layer().compact())
+                .with(filter)
+                .init();
+        }
+        LogFormat::json => {
+            registry()
+                .with(otel)
+                .with(layer().json())
+                .with(filter)
+                .init();
+        }
+    };
+
+    let (outgo_tx, income_rx, drained) = if !cfg.queue.disable {
+        cfg.queue
+            .split::<ChatMessage<Created>, Envelope<Created>>()
+            .await
+    } else {
+        (None, None, None)
+    };
+    let Some(income_rx) = income_rx else {
+        bail!("income channel invalid");
+    };
+    let Some(outgo_tx) = outgo_tx else {
+        bail!("outgo channel invalid");
+    };
+
+    let conf = cfg.agent;
+    let agent = Arc::new(Agent {
+        model: cfg.model,
+        tools: builtin(),
+        system: conf.system.clone(),
+        max_steps: conf.max_steps,
+    });
+    let limit = Arc::new(Semaphore::new(conf.concurrency.max(1)));
+    let mut running = JoinSet::new();
+    let mut rx = income_rx.lock().await;
+    info!("agent on {:?}", conf.event);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            x = rx.recv() => {
+                let Some(x) = x else { break };
+                let event = x.event().unwrap_or_default();
+                if !conf.event.iter().any(|p| glob(p.as_bytes(), event.as_bytes())) {
+                    debug!("skip {:?}", event);
+                    continue;
+                }
+                let Ok(permit) = limit.clone().acquire_owned().await else { break };
+                let span = info_span!("agent.run", sender = %x.sender, event);
+                if let Some(t) = &x.trace {
+                    t.attach(&span);
+                }
+                let (agent, tx, reply) = (agent.clone(), outgo_tx.clone(), conf.reply.clone());
+                running.spawn(async move {
+                    if let Err(e) = agent.reply(&x, &reply, &tx).await {
+                        error!("{}: {:#}", x.sender, e);
+                    }
+                    drop(permit);
+                }.instrument(span));
+            }
+            Some(_) = running.join_next(), if !running.is_empty() => {}
+            _ = &mut shutdown => break,
+        }
+    }
+
+    info!("Shutting down");
+    while running.join_next().await.is_some() {}
+    drop(outgo_tx);
+    if let Some(drained) = drained {
+        drained.wait().await;
+    }
+    if let Some(provider) = provider {
+        // Flushing blocks on the exporter
+        let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+    }
+    Ok(())
 }
@@ -0,0 +1,124 @@
+//! OpenAI-compatible chat completions with function calling.
+use anyhow::{Result, anyhow, bail};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Function {
+    pub name: String,
+    /// JSON text, as the model wrote it.
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn default_kind() -> String {
+    "function".to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_kind")]
+    pub kind: String,
+    pub function: Function,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Turn {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<String>,
+    /// What reasoning models think aloud, never sent back.
+    #[serde(default, alias = "reasoning_content", skip_serializing)]
+    pub reasoning: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Turn {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_owned(),
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    /// The answer to the call `id`.
+    pub fn tool(id: &str, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(id.to_owned()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+fn default_api_key_env() -> String {
+    "OPENAI_API_KEY".to_owned()
+}
+
+/// An OpenAI-compatible endpoint and model, the `model` table of the agent
+/// and the `llm` table of a chat logic section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Model {
+    /// Up to the version, `https://api.openai.com/v1`.
+    pub base_url: String,
+    pub model: String,
+    /// Read from the `api_key_env` variable when absent, none is sent if
+    /// that is unset too.
+    pub api_key: Option<String>,
+    #[serde(default = "default_api_key_env")]
+    pub api_key_env: String,
+    pub temperature: Option<f64>,
+}
+
+impl Model {
+    /// Posts `body` to the completions endpoint with the model and the
+    /// temperature set, failing with the answer on an error status.
+    pub async fn request(&self, mut body: Map<String, Value>) -> Result<Response> {
+        body.insert("model".into(), self.model.clone().into());
+        if let Some(t) = self.temperature {
+            body.insert("temperature".into(), t.into());
+        }
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let mut r = reqwest::Client::new().post(url).json(&body);
+        if let Some(key) = self
+            .api_key
+            .clone()
+            .or_else(|| std::env::var(&self.api_key_env).ok())
+        {
+            r = r.bearer_auth(key);
+        }
+        let resp = r.send().await?;
+        if !resp.status().is_success() {
+            bail!(
+                "{}: {}",
+                resp.status(),
+                resp.text().await.unwrap_or_default()
+            );
+        }
+        Ok(resp)
+    }
+
+    /// The next assistant turn after `messages`, which may call `tools`.
+    pub async fn complete(&self, messages: &[Turn], tools: &[Value]) -> Result<Turn> {
+        let mut body = Map::new();
+        body.insert("messages".into(), serde_json::to_value(messages)?);
+        if !tools.is_empty() {
+            body.insert("tools".into(), tools.into());
+        }
+        let mut v: Value = self.request(body).await?.json().await?;
+        if let Some(e) = v.get("error") {
+            bail!("{}", e);
+        }
+        let turn = v
+            .pointer_mut("/choices/0/message")
+            .map(Value::take)
+            .ok_or_else(|| anyhow!("no choice in the completion"))?;
+        Ok(serde_json::from_value(turn)?)
+    }
+}
@@ -0,0 +1,118 @@
+use super::model::{Model, Turn};
+use super::step::step_brick;
+use super::tool::Tools;
+use anyhow::{Result, bail};
+use message::{ChatMessage, Envelope};
+use serde_json::{Value, json};
+use short_uuid::ShortUuid;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, warn};
+
+/// What a run went through, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// What the model said or thought on the way to calling tools.
+    Thought(String),
+    Call {
+        name: String,
+        arguments: Value,
+        result: Result<Value, String>,
+    },
+    Answer(String),
+    /// The run failed, only `reply` shows it.
+    Fault(String),
+}
+
+pub struct Agent {
+    pub model: Model,
+    pub tools: Tools,
+    pub system: Option<String>,
+    /// Completions a run may take before it gives up.
+    pub max_steps: usize,
+}
+
+impl Agent {
+    /// Calls tools until the model answers `history`, handing every step to
+    /// `emit` as it is taken.
+    pub async fn run(&self, history: &[Turn], mut emit: impl FnMut(Step)) -> Result<String> {
+        let mut turns: Vec<Turn> = self.system.iter().map(|x| Turn::new("system", x)).collect();
+        turns.extend_from_slice(history);
+        let specs = self.tools.specs();
+
+        for _ in 0..self.max_steps.max(1) {
+            let mut turn = self.model.complete(&turns, &specs).await?;
+            let thought = turn
+                .reasoning
+                .take()
+                .into_iter()
+                .chain(turn.content.clone().filter(|_| !turn.tool_calls.is_empty()));
+            for x in thought.filter(|x| !x.trim().is_empty()) {
+                emit(Step::Thought(x));
+            }
+            if turn.tool_calls.is_empty() {
+                let answer = turn.content.unwrap_or_default();
+                emit(Step::Answer(answer.clone()));
+                return Ok(answer);
+            }
+
+            let calls = turn.tool_calls.clone();
+            turns.push(turn);
+            for call in calls {
+                let f = &call.function;
+                debug!("call {}({})", f.name, f.arguments);
+                let result = self.tools.call(&f.name, &f.arguments).await.map_err(|e| {
+                    warn!("{}: {:#}", f.name, e);
+                    e.to_string()
+                });
+                let content = match &result {
+                    Ok(v) => v.to_string(),
+                    Err(e) => json!({ "error": e }).to_string(),
+                };
+                turns.push(Turn::tool(&call.id, content));
+                emit(Step::Call {
+                    name: f.name.clone(),
+                    arguments: serde_json::from_str(&f.arguments)
+                        .unwrap_or_else(|_| f.arguments.clone().into()),
+                    result,
+                });
+            }
+        }
+        bail!("no answer after {} steps", self.max_steps)
+    }
+
+    /// Answers the `data` of `e`, streaming the steps of the run to its
+    /// sender as `event` bricks, sent by the model.
+    pub async fn reply<T: Default>(
+        &self,
+        e: &ChatMessage<T>,
+        event: &str,
+        tx: &UnboundedSender<Envelope<T>>,
+    ) -> Result<String> {
+        let ask = match e.content.get("data") {
+            Some(Value::String(x)) => x.clone(),
+            Some(x) => x.to_string(),
+            None => return Ok(String::new()),
+        };
+        let id = ShortUuid::generate().to_string();
+        let mut n = 0;
+        let mut emit = |step: Step| {
+            n += 1;
+            let content = step_brick(&id, n, event, e.channel.as_deref(), &step);
+            if let Ok(content) = serde_json::to_value(content) {
+                let _ = tx.send(Envelope {
+                    receiver: vec![e.sender.clone()],
+                    message: (self.model.model.as_str().into(), content).into(),
+                });
+            }
+        };
+        let r = self.run(&[Turn::new("user", ask)], &mut emit).await;
+        if let Err(x) = &r {
+            emit(Step::Fault(x.to_string()));
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+#[path = "runtime_test.rs"]
+mod tests;
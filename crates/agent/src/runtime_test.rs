@@ -0,0 +1,151 @@
+use super::*;
+use crate::builtin::builtin;
+use axum::{Json, Router, extract::State, routing::post};
+use brick::Brick;
+use content::{Content, Method};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::unbounded_channel;
+
+type Seen = Arc<Mutex<Vec<Value>>>;
+
+/// Answers the `n`th completion request with `replies[n]`, keeping the
+/// requests.
+async fn mock(replies: &'static [&'static str]) -> (Model, Seen) {
+    let seen = Seen::default();
+    let app = Router::new()
+        .route(
+            "/v1/chat/completions",
+            post(
+                move |State(seen): State<Seen>, Json(req): Json<Value>| async move {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(req);
+                    let reply = replies[(seen.len() - 1).min(replies.len() - 1)];
+                    Json(json!({
+                        "choices": [{"message": serde_json::from_str::<Value>(reply).unwrap()}]
+                    }))
+                },
+            ),
+        )
+        .with_state(seen.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let model = Model {
+        base_url: format!("http://{addr}/v1"),
+        model: "mock".into(),
+        api_key: None,
+        api_key_env: "MOCK_API_KEY_UNSET".into(),
+        temperature: None,
+    };
+    (model, seen)
+}
+
+const CALC: &str = r#"{
+    "role": "assistant",
+    "content": "Multiply first.",
+    "tool_calls": [{
+        "id": "c1",
+        "type": "function",
+        "function": {"name": "calc", "arguments": "{\"op\":\"mul\",\"a\":6,\"b\":7}"}
+    }]
+}"#;
+
+const ANSWER: &str = r#"{"role": "assistant", "content": "It is 42."}"#;
+
+fn agent(model: Model, max_steps: usize) -> Agent {
+    Agent {
+        model,
+        tools: builtin(),
+        system: Some("be brief".into()),
+        max_steps,
+    }
+}
+
+#[tokio::test]
+async fn tool_then_answer() {
+    let (model, seen) = mock(&[CALC, ANSWER]).await;
+    let mut steps = Vec::new();
+    let answer = agent(model, 4)
+        .run(&[Turn::new("user", "6 times 7?")], |x| steps.push(x))
+        .await
+        .unwrap();
+    assert_eq!(answer, "It is 42.");
+    assert_eq!(
+        steps,
+        [
+            Step::Thought("Multiply first.".into()),
+            Step::Call {
+                name: "calc".into(),
+                arguments: json!({"op": "mul", "a": 6, "b": 7}),
+                result: Ok(json!(42.0)),
+            },
+            Step::Answer("It is 42.".into()),
+        ]
+    );
+
+    let seen = seen.lock().unwrap();
+    let names: Vec<&str> = seen[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|t| t.pointer("/function/name")?.as_str())
+        .collect();
+    assert_eq!(names, ["now", "calc"]);
+    assert_eq!(
+        seen[0].pointer("/tools/1/function/parameters/properties/op/enum"),
+        Some(&json!(["add", "sub", "mul", "div"]))
+    );
+    // system, user, the call and its result
+    let messages = seen[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[2]["tool_calls"][0]["id"], "c1");
+    assert_eq!(messages[3]["role"], "tool");
+    assert_eq!(messages[3]["tool_call_id"], "c1");
+    assert_eq!(messages[3]["content"], "42.0");
+}
+
+#[tokio::test]
+async fn give_up_after_max_steps() {
+    let (model, _) = mock(&[CALC]).await;
+    let mut calls = 0;
+    let e = agent(model, 3)
+        .run(&[Turn::new("user", "loop")], |x| {
+            calls += matches!(x, Step::Call { .. }) as usize
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(calls, 3);
+    assert!(e.to_string().contains("3 steps"), "{e}");
+}
+
+#[tokio::test]
+async fn reply_steps_as_bricks() {
+    let (model, _) = mock(&[CALC, ANSWER]).await;
+    let (tx, mut rx) = unbounded_channel::<Envelope<()>>();
+    let e: ChatMessage<()> = (
+        "s1".into(),
+        json!({"event": "agent", "channel": "general", "data": "6 times 7?"}),
+    )
+        .into();
+    agent(model, 4).reply(&e, "chat", &tx).await.unwrap();
+
+    drop(tx);
+    let mut kinds = Vec::new();
+    while let Some(x) = rx.recv().await {
+        assert_eq!(x.receiver, vec!["s1".into()]);
+        let Content::Join(x) = serde_json::from_value::<Content<Brick>>(x.message.content).unwrap()
+        else {
+            panic!("not a join");
+        };
+        assert_eq!(x.method, Method::Replace);
+        assert_eq!(x.channel.as_deref(), Some("general"));
+        kinds.push(match x.data {
+            Brick::fold(_) => "fold",
+            Brick::table(_) => "table",
+            Brick::text(_) => "text",
+            _ => "other",
+        });
+    }
+    assert_eq!(kinds, ["fold", "table", "text"]);
+}
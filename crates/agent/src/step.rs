@@ -0,0 +1,152 @@
+//! Steps as the UI shows them, each its own brick in the list of the event.
+use super::runtime::Step;
+use brick::{
+    Bind, BindVariant, Brick, Fold, FoldAttr, Table, Tbody, Td, Text, TextAttr, Th, Thead, Tr,
+};
+use content::{Content, Influx, Method};
+use maplit::hashmap;
+use serde_json::Value;
+
+fn text(id: Option<String>, value: String, selector: &str, format: Option<&str>) -> Brick {
+    Brick::text(Text {
+        id,
+        attrs: Some(TextAttr {
+            format: format.map(str::to_owned),
+            selector: Some(selector.to_owned()),
+            ..Default::default()
+        }),
+        bind: Some(hashmap! {
+            "value".to_owned() => Bind {
+                variant: BindVariant::Default {},
+                default: Some(value.into()),
+                ..Default::default()
+            }
+        }),
+    })
+}
+
+fn cell(v: &Value) -> String {
+    match v {
+        Value::String(x) => x.clone(),
+        Value::Null => String::new(),
+        x => x.to_string(),
+    }
+}
+
+fn row(cells: Vec<String>, head: bool) -> Brick {
+    let sub = cells
+        .into_iter()
+        .map(|x| {
+            let sub = Some(vec![text(None, x, "cell", None)]);
+            if head {
+                Brick::th(Th { id: None, sub })
+            } else {
+                Brick::td(Td { id: None, sub })
+            }
+        })
+        .collect();
+    Brick::tr(Tr {
+        id: None,
+        sub: Some(sub),
+    })
+}
+
+/// Columns and rows of a tool result: the keys of a list of objects, the
+/// entries of an object, else the value on its own.
+fn grid(v: &Value) -> (Vec<String>, Vec<Vec<String>>) {
+    match v {
+        Value::Array(xs) if !xs.is_empty() && xs.iter().all(Value::is_object) => {
+            let mut columns: Vec<String> = Vec::new();
+            for k in xs
+                .iter()
+                .filter_map(Value::as_object)
+                .flat_map(|m| m.keys())
+            {
+                if !columns.contains(k) {
+                    columns.push(k.clone());
+                }
+            }
+            let rows = xs
+                .iter()
+                .map(|x| columns.iter().map(|k| cell(&x[k])).collect())
+                .collect();
+            (columns, rows)
+        }
+        Value::Array(xs) => (
+            vec!["value".to_owned()],
+            xs.iter().map(|x| vec![cell(x)]).collect(),
+        ),
+        Value::Object(m) => (
+            vec!["key".to_owned(), "value".to_owned()],
+            m.iter().map(|(k, x)| vec![k.clone(), cell(x)]).collect(),
+        ),
+        x => (vec!["result".to_owned()], vec![vec![cell(x)]]),
+    }
+}
+
+fn table(id: String, name: &str, arguments: &Value, result: &Result<Value, String>) -> Brick {
+    let (columns, rows) = match result {
+        Ok(v) => grid(v),
+        Err(e) => (vec!["error".to_owned()], vec![vec![e.clone()]]),
+    };
+    let call = format!("{name}({})", cell(arguments));
+    Brick::table(Table {
+        id: Some(id),
+        sub: Some(vec![
+            Brick::thead(Thead {
+                id: None,
+                sub: Some(vec![row(vec![call], true), row(columns, true)]),
+            }),
+            Brick::tbody(Tbody {
+                id: None,
+                sub: Some(rows.into_iter().map(|x| row(x, false)).collect()),
+            }),
+        ]),
+    })
+}
+
+/// The `n`th step of the run `run`. The answer takes the id of the run
+/// itself, the steps before it `<run>.<n>`.
+pub fn step_brick(
+    run: &str,
+    n: usize,
+    event: &str,
+    channel: Option<&str>,
+    step: &Step,
+) -> Content<Brick> {
+    let id = format!("{run}.{n}");
+    let data = match step {
+        Step::Thought(x) => Brick::fold(Fold {
+            id: Some(id),
+            attrs: Some(FoldAttr {
+                class: Some(vec!["reasoning".to_owned()]),
+                ..Default::default()
+            }),
+            item: Some(vec![text(None, "Thinking".to_owned(), "summary", None)]),
+            sub: Some(vec![text(None, x.clone(), "reasoning", Some("md"))]),
+            ..Default::default()
+        }),
+        Step::Call {
+            name,
+            arguments,
+            result,
+        } => table(id, name, arguments, result),
+        Step::Answer(x) => text(Some(run.to_owned()), x.clone(), "answer", Some("md")),
+        Step::Fault(x) => {
+            let mut fault = text(Some(run.to_owned()), x.clone(), "answer", None);
+            if let Brick::text(Text {
+                attrs: Some(attrs), ..
+            }) = &mut fault
+            {
+                attrs.class = Some(vec!["error".to_owned()]);
+            }
+            fault
+        }
+    };
+    Content::Join(Influx {
+        event: event.into(),
+        channel: channel.map(str::to_owned),
+        data,
+        method: Method::Replace,
+    })
+}
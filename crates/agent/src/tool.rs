@@ -0,0 +1,95 @@
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use schemars::{JsonSchema, generate::SchemaSettings};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+type Call = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<Value>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Tool {
+    name: String,
+    description: String,
+    parameters: Value,
+    call: Call,
+}
+
+/// Functions the model may call, each taking the arguments `A` it describes
+/// with their JSON Schema.
+#[derive(Clone, Default)]
+pub struct Tools {
+    tools: Vec<Tool>,
+}
+
+/// The schema of `A` as a function's `parameters`, without references as
+/// not every server resolves them.
+fn parameters<A: JsonSchema>() -> Value {
+    let mut v = SchemaSettings::draft2020_12()
+        .with(|s| s.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<A>()
+        .to_value();
+    if let Some(m) = v.as_object_mut() {
+        m.remove("$schema");
+        m.remove("title");
+    }
+    v
+}
+
+impl Tools {
+    pub fn register<A, F, Fut>(&mut self, name: &str, description: &str, f: F) -> &mut Self
+    where
+        A: DeserializeOwned + JsonSchema,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        let call: Call = Arc::new(move |v| match serde_json::from_value(v) {
+            Ok(x) => Box::pin(f(x)),
+            Err(e) => Box::pin(async move { Err(e.into()) }),
+        });
+        self.tools.retain(|t| t.name != name);
+        self.tools.push(Tool {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            parameters: parameters::<A>(),
+            call,
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// The `tools` of a chat completion request.
+    pub fn specs(&self) -> Vec<Value> {
+        self.tools
+            .iter()
+            .map(|t| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": t.parameters,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Runs `name` on `arguments`, the JSON text the model wrote.
+    pub async fn call(&self, name: &str, arguments: &str) -> Result<Value> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| anyhow!("no tool {name}"))?;
+        let v = match arguments.trim() {
+            "" => json!({}),
+            x => serde_json::from_str(x)?,
+        };
+        (tool.call)(v).await
+    }
+}
@@ -732,6 +732,12 @@ impl Brick {
     }
 }
 
+/// JSON Schema of a `Brick` tree, for models asked to produce one.
+#[cfg(feature = "schema")]
+pub fn schema() -> Value {
+    schemars::schema_for!(Brick).to_value()
+}
+
 /*
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1 @@
+create index message_channel_created on message(channel_id, created desc);
@@ -0,0 +1 @@
+alter table session add column channel_id integer references channel (id);
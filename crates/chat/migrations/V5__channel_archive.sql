@@ -0,0 +1,2 @@
+alter table channel add column archived boolean not null default false;
+create index channel_parent_idx on channel(parent_id);
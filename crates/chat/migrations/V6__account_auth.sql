@@ -0,0 +1,12 @@
+-- Tokens are mailed, only their SHA-256 is kept
+create table magic_token (
+    token_hash bytea primary key,
+    account_id integer not null references account(id),
+    created timestamp not null default now(),
+    used timestamp
+);
+create index magic_token_a on magic_token(account_id);
+
+update session set updated = coalesce(updated, created, now());
+alter table session alter column updated set default now();
+create index session_updated on session(updated);
@@ -0,0 +1,12 @@
+-- `user` for what accounts wrote, `assistant` for the answers of a model,
+-- which have no account
+alter table message add column role text not null default 'user';
+
+-- What a model was told of the messages up to `upto`, so it is not
+-- told them again
+create table channel_summary (
+    channel_id integer primary key references channel (id),
+    summary text not null,
+    upto timestamp not null,
+    updated timestamp not null default now()
+);
@@ -1,6 +1,9 @@
+use super::auth::auth_router;
+use super::channel::{channel_router, list_layout};
 use super::config::ASSETS_PATH;
-use super::db::{Account, Channel, CreateChan, JoinChan};
-use super::error::HttpResult;
+use super::db::{Account, Channel, ChatRecord, JoinChan};
+use super::error::{HttpResult, mkerr};
+use super::logic::message_brick;
 use super::shared::{Db, Shared};
 use async_fs::read_to_string;
 use axum::{
@@ -8,17 +11,16 @@ use axum::{
     extract::{Path, Query, State},
     routing::{get, post},
 };
-use brick::{Bind, BindVariant, Brick, Text, TextAttr};
-use content::{Content, Influx, Method};
-use maplit::hashmap;
-use message::session::SessionInfo;
+use message::{ChatMessage, session::SessionInfo, time::Created};
 use serde::Deserialize;
-use serde_json::{Map, Value, json};
-use short_uuid::ShortUuid;
-use std::borrow::Cow;
+use serde_json::{Value, json};
+use sqlx::types::chrono::NaiveDateTime;
 use std::path::Path as OsPath;
 use tracing::info;
 
+const HISTORY_LIMIT: i64 = 50;
+const HISTORY_MAX: i64 = 500;
+
 #[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct Opts {
@@ -40,41 +42,25 @@ async fn user(
     Ok(Json(x))
 }
 
+/// `channel::select` hook, the message `data` is the channel name or id.
 async fn select_chan(
     opts: Query<Opts>,
     State(db): State<Db>,
-    Json(select): Json<Value>,
+    Json(select): Json<ChatMessage<Created>>,
 ) -> HttpResult<Json<Value>> {
-    let chan = Channel::default();
     info!("select_chan {:?}", &select);
-    if let Some(layout) = opts.layout
-        && layout
-    {
-        let content: Vec<_> = ["1", "4", "5", "6"]
-            .iter()
-            .map(|x| {
-                Content::Join(Influx {
-                    event: "channel::list".into(),
-                    channel: None,
-                    method: Method::Replace,
-                    data: Brick::text(Text {
-                        id: Some(x.to_string()),
-                        attrs: Some(TextAttr {
-                            class: Some(vec!["box".to_string()]),
-                            ..Default::default()
-                        }),
-                        bind: Some(hashmap! {
-                            "value".to_owned() => Bind {
-                                variant: BindVariant::Default {  },
-                                default: Some(serde_json::to_value(x).unwrap()),
-                                ..Default::default()
-                            }
-                        }),
-                    }),
-                })
-            })
-            .collect();
-        Ok(Json(serde_json::to_value(content)?))
+    let name = match select.content.get("data") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        _ => return mkerr("channel::select needs a channel name or id".into()),
+    };
+    let Some(chan) = db.select_channel((&select.sender).into(), &name).await? else {
+        return mkerr(format!("not a member of channel {}", name));
+    };
+    if opts.layout.unwrap_or_default() {
+        // The new channel's recent messages
+        let messages = db.list_message(chan.id, None, HISTORY_LIMIT).await?;
+        Ok(Json(history_layout(&chan, &messages)?))
     } else {
         Ok(Json(serde_json::to_value(chan)?))
     }
@@ -98,65 +84,54 @@ async fn channel(
     if let Some(layout) = opts.layout
         && layout
     {
-        let content: Vec<_> = ["1", "2", "3"]
-            .iter()
-            .map(|x| {
-                Content::Join(Influx {
-                    event: "channel::list".into(),
-                    channel: None,
-                    method: Method::Replace,
-                    data: Brick::text(Text {
-                        id: Some(x.to_string()),
-                        attrs: Some(TextAttr {
-                            class: Some(vec!["box".to_string()]),
-                            ..Default::default()
-                        }),
-                        bind: Some(hashmap! {
-                            "value".to_owned() => Bind {
-                                variant: BindVariant::Default {  },
-                                default: Some(serde_json::to_value(x).unwrap()),
-                                ..Default::default()
-                            }
-                        }),
-                    }),
-                })
-            })
-            .collect();
+        let content = list_layout(&channel);
         Ok(Json(serde_json::to_value(content)?))
     } else {
         Ok(Json(serde_json::to_value(channel)?))
     }
 }
 
+#[derive(Deserialize)]
+pub struct HistoryOpts {
+    pub layout: Option<bool>,
+    /// Name or id, the active channel of the session when absent.
+    pub channel: Option<String>,
+    /// Cursor, the `created` of the oldest message already shown.
+    pub before: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
 async fn history(
-    opts: Query<Opts>,
+    opts: Query<HistoryOpts>,
     State(db): State<Db>,
     Json(session): Json<SessionInfo>,
 ) -> HttpResult<Json<Value>> {
     info!(">> history: {:?}", &session);
-    if let Some(layout) = opts.layout
-        && layout
-    {
-        let content = Content::Join(Influx {
-            event: "chat-history".into(),
-            channel: None,
-            data: Brick::text(Text {
-                attrs: Some(TextAttr {
-                    format: Some("md".to_string()),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }),
-            method: Method::Concat,
-        });
-        //let msg: Message = ("chat".into(), content).into();
-        let r = serde_json::to_value(&content)?;
-        Ok(Json(r))
+    let Some(channel) = db
+        .current_channel((&session.id).into(), opts.channel.as_deref())
+        .await?
+    else {
+        return Ok(Json(Value::Array(Vec::new())));
+    };
+    let limit = opts.limit.unwrap_or(HISTORY_LIMIT).clamp(1, HISTORY_MAX);
+    let messages = db.list_message(channel.id, opts.before, limit).await?;
+    if opts.layout.unwrap_or_default() {
+        Ok(Json(history_layout(&channel, &messages)?))
     } else {
-        Ok(Json(Value::Array(Vec::new())))
+        Ok(Json(serde_json::to_value(messages)?))
     }
 }
 
+fn history_layout(channel: &Channel, messages: &[ChatRecord]) -> serde_json::Result<Value> {
+    // Oldest first, as they are appended
+    let content: Vec<_> = messages
+        .iter()
+        .rev()
+        .map(|m| message_brick(&m.content, &channel.name))
+        .collect();
+    serde_json::to_value(content)
+}
+
 async fn yaml(opts: Query<Opts>, Path(name): Path<String>) -> HttpResult<Json<serde_yaml::Value>> {
     let path = OsPath::new(ASSETS_PATH);
     let content = read_to_string(path.join(&name)).await?;
@@ -164,55 +139,14 @@ async fn yaml(opts: Query<Opts>, Path(name): Path<String>) -> HttpResult<Json<se
     Ok(Json(v))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct LoginOpts {
-    pub key: Option<String>,
-}
-
-async fn login(
-    opts: Query<LoginOpts>,
-    State(db): State<Db>,
-    Json(mut payload): Json<Map<String, Value>>,
-) -> HttpResult<Json<SessionInfo>> {
-    let tk = match &opts.key {
-        Some(key) => key,
-        None => "token",
-    };
-    let token = if let Some(token) = payload.get(tk) {
-        Cow::Borrowed(token.as_str().unwrap_or(""))
-    } else {
-        Cow::Owned(ShortUuid::generate().to_string())
-    };
-    let (id, name) = db.login(&token).await?;
-    info!(
-        "login {}: {}\ntoken : {:?}\n{:?}",
-        id, name, &token, payload
-    );
-    payload.insert("username".into(), name.into());
-    Ok(Json(SessionInfo {
-        id: id.as_str().into(),
-        info: payload,
-    }))
-}
-
-async fn logout(
-    _opts: Query<Opts>,
-    State(db): State<Db>,
-    Json(session): Json<SessionInfo>,
-) -> HttpResult<Json<SessionInfo>> {
-    db.logout(&session.id).await?;
-    info!("logout: {}", session.id);
-    Ok(Json(session))
-}
-
 pub fn data_router() -> Router<Shared> {
     Router::new()
-        .route("/login", post(login))
-        .route("/logout", post(logout))
+        .merge(auth_router())
         .route("/yaml/{name}", post(yaml))
         .route("/channel", post(channel))
         .route("/channel/select", post(select_chan))
         .route("/channel/join", post(join_chan))
+        .nest("/channel", channel_router())
         .route("/history", post(history))
         .route("/users", get(users))
         .route("/user/{user}", get(user))
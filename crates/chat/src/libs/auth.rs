@@ -0,0 +1,180 @@
+//! Account authentication behind the gateway `login` hook. The hook posts the
+//! connection query and gets the `SessionInfo` back, the session id doubling
+//! as the token to resume it with.
+use super::config::Auth;
+use super::db::Account;
+use super::error::{HttpResult, mkerr};
+use super::shared::{Db, Shared};
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    routing::post,
+};
+use message::session::SessionInfo;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use short_uuid::ShortUuid;
+use tokio::task::spawn_blocking;
+use tracing::{debug, info, warn};
+
+const PASSWORD_MIN: usize = 8;
+
+/// Verified against when there is no password to verify, so an unknown login
+/// takes as long as a wrong password.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$Y1kfHfZQNnhd2W00RROeFQ$TxKpt8ZOUcM2kjvoEr0DJMh70UaLd/ciPDKEIByApfA";
+
+async fn hash(password: String) -> anyhow::Result<String> {
+    spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    })
+    .await?
+}
+
+/// The account behind `login`, by name or email, if `password` matches.
+async fn verify(db: &Db, login: &str, password: String) -> HttpResult<Option<i32>> {
+    let (id, hash) = match db.password_of(login).await? {
+        Some((id, Some(hash))) => (Some(id), hash),
+        _ => (None, DUMMY_HASH.to_owned()),
+    };
+    let ok = spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|h| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &h)
+                .is_ok()
+        })
+    })
+    .await?;
+    Ok(id.filter(|_| ok))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginOpts {
+    /// Query key of the session token, `token` by default.
+    pub key: Option<String>,
+}
+
+/// Resumes the session of `token`, else opens one for a `magic` token or
+/// `username` and `password`, else for a guest when enabled.
+async fn login(
+    opts: Query<LoginOpts>,
+    State(db): State<Db>,
+    State(auth): State<Auth>,
+    Json(mut payload): Json<Map<String, Value>>,
+) -> HttpResult<Json<SessionInfo>> {
+    let text = |k: &str| payload.get(k).and_then(Value::as_str).map(str::to_owned);
+    let token = text(opts.key.as_deref().unwrap_or("token"));
+    let (magic, username, password) = (text("magic"), text("username"), text("password"));
+
+    let resumed = match &token {
+        Some(token) => db.resume_session(token, auth.session_ttl).await?,
+        None => None,
+    };
+    let (id, name) = if let Some(session) = resumed {
+        session
+    } else {
+        let account = if let Some(magic) = &magic {
+            db.redeem_magic(magic, auth.magic_ttl).await?
+        } else if let (Some(username), Some(password)) = (&username, password) {
+            verify(&db, username, password).await?
+        } else if auth.guest {
+            let name = format!("guest-{}", ShortUuid::generate());
+            Some(db.guest(&name).await?)
+        } else {
+            None
+        };
+        let Some(account) = account else {
+            return mkerr("invalid or expired credentials".into());
+        };
+        db.open_session(&ShortUuid::generate().to_string(), account)
+            .await?
+    };
+
+    // Not the session id, it resumes the session
+    info!("login: {}", name);
+    payload.remove("password");
+    payload.remove("magic");
+    payload.insert("username".into(), name.into());
+    payload.insert("token".into(), id.as_str().into());
+    Ok(Json(SessionInfo {
+        id: id.as_str().into(),
+        info: payload,
+    }))
+}
+
+async fn logout(
+    State(db): State<Db>,
+    State(auth): State<Auth>,
+    Json(session): Json<SessionInfo>,
+) -> HttpResult<Json<SessionInfo>> {
+    db.logout(&session.id, &auth).await?;
+    let name = session.info.get("username").and_then(Value::as_str);
+    info!("logout: {}", name.unwrap_or_default());
+    Ok(Json(session))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Register {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+}
+
+async fn register(State(db): State<Db>, Json(req): Json<Register>) -> HttpResult<Json<Account>> {
+    if req.password.chars().count() < PASSWORD_MIN {
+        return mkerr(format!("password needs {PASSWORD_MIN} characters at least"));
+    }
+    let hash = hash(req.password).await?;
+    let Some(account) = db.register(&req.name, &req.email, &hash).await? else {
+        return mkerr(format!("{} is taken", req.name));
+    };
+    info!("register: {}", account.name);
+    Ok(Json(account))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Magic {
+    /// Account name or email.
+    pub login: String,
+}
+
+/// Issues a magic token for the `magic` login query key. The answer is the
+/// same whether the account exists or not. Without a mailer to deliver them
+/// no tokens are issued.
+async fn magic(
+    State(db): State<Db>,
+    State(auth): State<Auth>,
+    Json(req): Json<Magic>,
+) -> HttpResult<Json<Value>> {
+    let Some(mailer) = &auth.mailer else {
+        debug!("magic token for {}: no mailer", req.login);
+        return mkerr("magic tokens are not enabled".into());
+    };
+    let token = ShortUuid::generate().to_string();
+    if let Some((name, email)) = db.issue_magic(&req.login, &token).await? {
+        let r = reqwest::Client::new()
+            .post(mailer)
+            .json(&json!({"name": name, "email": email, "token": token}))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = r {
+            warn!("deliver magic token to {}: {}", name, e);
+        }
+    }
+    Ok(Json(true.into()))
+}
+
+pub fn auth_router() -> Router<Shared> {
+    Router::new()
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/register", post(register))
+        .route("/magic", post(magic))
+}
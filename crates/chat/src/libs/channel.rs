@@ -0,0 +1,251 @@
+//! Channel lifecycle. Every endpoint takes either the request itself or the
+//! `ChatMessage` of the matching UI event (`channel::create`, ...) as posted
+//! by a gateway hook, where the sender is the session and `data` the request.
+use super::admin::Opts;
+use super::db::{ArchiveChan, ChanRef, Channel, CreateChan, Member, RenameChan, TransferChan};
+use super::error::{HttpResult, mkerr};
+use super::shared::{Db, Shared};
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    routing::post,
+};
+use brick::{Bind, BindVariant, Brick, Text, TextAttr};
+use content::{Content, Influx, Method};
+use maplit::hashmap;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A form field comes as `{ "data": .., "payload": .. }`, left empty as `""`.
+fn field(v: Value) -> Value {
+    match v {
+        Value::Object(mut f) if f.contains_key("data") => match f.remove("data") {
+            Some(Value::String(s)) if s.is_empty() => Value::Null,
+            Some(x) => x,
+            None => Value::Null,
+        },
+        v => v,
+    }
+}
+
+fn request<T: DeserializeOwned>(v: Value) -> serde_json::Result<T> {
+    let v = match v {
+        Value::Object(mut m) if m.contains_key("sender") && m.contains_key("content") => {
+            let mut data = m
+                .get_mut("content")
+                .and_then(|c| c.get_mut("data"))
+                .map(Value::take)
+                .unwrap_or_default();
+            if let Some(d) = data.as_object_mut() {
+                for v in d.values_mut() {
+                    *v = field(v.take());
+                }
+                if let Some(s) = m.remove("sender") {
+                    d.insert("session".into(), s);
+                }
+            }
+            data
+        }
+        v => v,
+    };
+    serde_json::from_value(v)
+}
+
+fn item(event: &str, id: i32, text: String, method: Method) -> Content<Brick> {
+    Content::Join(Influx {
+        event: event.into(),
+        channel: None,
+        method,
+        data: Brick::text(Text {
+            id: Some(id.to_string()),
+            attrs: Some(TextAttr {
+                class: Some(vec!["box".to_string()]),
+                ..Default::default()
+            }),
+            bind: Some(hashmap! {
+                "value".to_owned() => Bind {
+                    variant: BindVariant::Default {},
+                    default: Some(text.into()),
+                    ..Default::default()
+                }
+            }),
+        }),
+    })
+}
+
+/// `channel::list`, in the depth-first order of `list_channel`, sub-channels
+/// marked with their depth.
+pub fn list_layout(channels: &[Channel]) -> Vec<Content<Brick>> {
+    let parent: HashMap<i32, Option<i32>> = channels.iter().map(|c| (c.id, c.parent_id)).collect();
+    channels
+        .iter()
+        .map(|c| {
+            let mut depth = 0;
+            let mut p = c.parent_id;
+            // Bounded, a parent outside the list ends the walk
+            while let Some(id) = p
+                && let Some(up) = parent.get(&id)
+                && depth < channels.len()
+            {
+                depth += 1;
+                p = *up;
+            }
+            let mut text = format!("{}{}", "› ".repeat(depth), c.name);
+            if c.archived {
+                text.push_str(" (archived)");
+            }
+            item("channel::list", c.id, text, Method::Replace)
+        })
+        .collect()
+}
+
+fn members_layout(members: &[Member]) -> Vec<Content<Brick>> {
+    members
+        .iter()
+        .map(|m| {
+            let text = if m.owner {
+                format!("{} (owner)", m.name)
+            } else {
+                m.name.clone()
+            };
+            item("channel::members", m.id, text, Method::Replace)
+        })
+        .collect()
+}
+
+/// The refreshed channel list, preceded by `removed` being dropped from it.
+async fn reply(
+    opts: &Opts,
+    db: &Db,
+    session: &str,
+    removed: Option<i32>,
+    plain: Value,
+) -> HttpResult<Json<Value>> {
+    if !opts.layout.unwrap_or_default() {
+        return Ok(Json(plain));
+    }
+    let channels = db.list_channel(session).await?;
+    let mut content = Vec::new();
+    if let Some(id) = removed {
+        content.push(item("channel::list", id, String::new(), Method::Delete));
+    }
+    content.extend(list_layout(&channels));
+    Ok(Json(serde_json::to_value(content)?))
+}
+
+async fn create(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: CreateChan = request(v)?;
+    let Some(chan) = db.create_channel(&req).await? else {
+        return mkerr(format!("cannot create {} under {:?}", req.name, req.parent));
+    };
+    reply(&opts, &db, &req.session, None, serde_json::to_value(chan)?).await
+}
+
+async fn rename(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: RenameChan = request(v)?;
+    let Some(chan) = db.rename_channel(&req).await? else {
+        return mkerr(format!("not the owner of {}", req.channel));
+    };
+    reply(&opts, &db, &req.session, None, serde_json::to_value(chan)?).await
+}
+
+async fn archive(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: ArchiveChan = request(v)?;
+    let Some(chan) = db.archive_channel(&req).await? else {
+        return mkerr(format!("not the owner of {}", req.channel));
+    };
+    reply(&opts, &db, &req.session, None, serde_json::to_value(chan)?).await
+}
+
+async fn delete(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: ChanRef = request(v)?;
+    // Looked up first, the id is gone afterwards
+    let chan = db.current_channel(&req.session, Some(&req.channel)).await?;
+    if !db.delete_channel(&req).await? {
+        return mkerr(format!("not the owner of {}", req.channel));
+    }
+    reply(&opts, &db, &req.session, chan.map(|c| c.id), true.into()).await
+}
+
+async fn leave(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: ChanRef = request(v)?;
+    let chan = db.current_channel(&req.session, Some(&req.channel)).await?;
+    if !db.leave_channel(&req).await? {
+        return mkerr(format!(
+            "not in {}, or its owner with other members left",
+            req.channel
+        ));
+    }
+    reply(&opts, &db, &req.session, chan.map(|c| c.id), true.into()).await
+}
+
+async fn members(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: ChanRef = request(v)?;
+    let members = db.list_member(&req).await?;
+    if opts.layout.unwrap_or_default() {
+        Ok(Json(serde_json::to_value(members_layout(&members))?))
+    } else {
+        Ok(Json(serde_json::to_value(members)?))
+    }
+}
+
+async fn transfer(
+    Query(opts): Query<Opts>,
+    State(db): State<Db>,
+    Json(v): Json<Value>,
+) -> HttpResult<Json<Value>> {
+    let req: TransferChan = request(v)?;
+    if !db.transfer_channel(&req).await? {
+        return mkerr(format!(
+            "{} is not a member of {}, or you are not its owner",
+            req.account, req.channel
+        ));
+    }
+    let members = db
+        .list_member(&ChanRef {
+            session: req.session,
+            channel: req.channel,
+        })
+        .await?;
+    if opts.layout.unwrap_or_default() {
+        Ok(Json(serde_json::to_value(members_layout(&members))?))
+    } else {
+        Ok(Json(serde_json::to_value(members)?))
+    }
+}
+
+pub fn channel_router() -> Router<Shared> {
+    Router::new()
+        .route("/create", post(create))
+        .route("/rename", post(rename))
+        .route("/archive", post(archive))
+        .route("/delete", post(delete))
+        .route("/leave", post(leave))
+        .route("/members", post(members))
+        .route("/transfer", post(transfer))
+}
@@ -5,7 +5,10 @@ use figment::{
 };
 use indexmap::IndexMap;
 use message::config::Queue;
+use message::server::Server;
+use message::trace::Otlp;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use serde_with::{OneOrMany, serde_as};
 use std::ops::Deref;
 
@@ -55,9 +58,10 @@ pub enum LogFormat {
 #[derive(Debug, Deserialize, Clone)]
 pub struct Log {
     pub format: LogFormat,
+    pub otlp: Option<Otlp>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Copy)]
 pub enum Logic {
     #[serde(rename = "chat")]
     Chat,
@@ -65,6 +69,111 @@ pub enum Logic {
     Crm,
     #[serde(rename = "echo")]
     Echo,
+    #[serde(rename = "design")]
+    Design,
+}
+
+fn default_event() -> Vec<String> {
+    vec!["*".to_owned()]
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+fn default_timeout() -> u64 {
+    120
+}
+
+fn default_error_event() -> String {
+    "chat".to_owned()
+}
+
+/// What a handler processes in order, messages of different keys run in parallel.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderBy {
+    #[default]
+    Session,
+    /// The channel named by the message, else the active one of its session,
+    /// its session when it has no channel.
+    Channel,
+}
+
+/// `[logic.<name>]`, one handler of the registry.
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct LogicConf {
+    #[serde(default)]
+    pub disable: bool,
+    /// `chat`, `crm`, `echo` or `design`, the section name when absent, so
+    /// several sections can run the same handler with different settings.
+    pub kind: Option<Logic>,
+    /// Globs on the event name, every event by default.
+    #[serde(default = "default_event")]
+    #[serde_as(as = "OneOrMany<_>")]
+    pub event: Vec<String>,
+    /// Messages handled at once.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Seconds before a message is given up on, 0 for no limit.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub order: OrderBy,
+    /// Failures are reported to the sender as an error brick on this event.
+    #[serde(default = "default_error_event")]
+    pub error_event: String,
+    /// The rest of the section, handed to the handler.
+    #[serde(flatten)]
+    pub settings: Map<String, Value>,
+}
+
+impl Default for LogicConf {
+    fn default() -> Self {
+        Self {
+            disable: false,
+            kind: None,
+            event: default_event(),
+            concurrency: default_concurrency(),
+            timeout: default_timeout(),
+            order: OrderBy::default(),
+            error_event: default_error_event(),
+            settings: Map::new(),
+        }
+    }
+}
+
+impl LogicConf {
+    pub fn kind(&self, name: &str) -> serde_json::Result<Logic> {
+        match self.kind {
+            Some(kind) => Ok(kind),
+            None => serde_json::from_value(name.into()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Logics {
+    /// `logic = 'chat'`, a single handler for every event.
+    One(Logic),
+    Many(IndexMap<String, LogicConf>),
+}
+
+impl Logics {
+    pub fn sections(self) -> IndexMap<String, LogicConf> {
+        match self {
+            Logics::One(kind) => {
+                let conf = LogicConf {
+                    kind: Some(kind),
+                    ..Default::default()
+                };
+                IndexMap::from([(format!("{kind:?}").to_lowercase(), conf)])
+            }
+            Logics::Many(x) => x,
+        }
+    }
 }
 
 fn default_accept() -> String {
@@ -119,17 +228,63 @@ pub type HookMap = IndexMap<String, Hooks>;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Gateway {
     pub base_url: String,
+    /// Sent as bearer token to the gateway `/config` API.
+    pub api_key: Option<String>,
+}
+
+fn default_session_ttl() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_magic_ttl() -> u64 {
+    15 * 60
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Auth {
+    /// Seconds a session stays valid after its last use.
+    #[serde(default = "default_session_ttl")]
+    pub session_ttl: u64,
+    /// Seconds a magic token can be redeemed.
+    #[serde(default = "default_magic_ttl")]
+    pub magic_ttl: u64,
+    /// Receives `{name, email, token}` to deliver a magic token, none are
+    /// issued when unset.
+    pub mailer: Option<String>,
+    /// Connections without credentials get a fresh unregistered account
+    /// instead of being rejected, swept at logout once unused.
+    #[serde(default)]
+    pub guest: bool,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            session_ttl: default_session_ttl(),
+            magic_ttl: default_magic_ttl(),
+            mailer: None,
+            guest: false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[allow(unused)]
 pub struct Config {
-    pub logic: Logic,
+    pub logic: Logics,
     pub queue: Queue,
     pub database: Database,
     pub trace: Log,
     pub gateway: Gateway,
     pub hooks: HookMap,
+    #[serde(default = "default_server")]
+    pub server: Server,
+    #[serde(default)]
+    pub auth: Auth,
+}
+
+fn default_server() -> Server {
+    Server::on("0.0.0.0:3003")
 }
 
 impl Config {
@@ -1,13 +1,13 @@
+use super::config::Auth;
 use futures::TryStreamExt;
 use indoc::indoc;
 use serde::{Deserialize, Serialize};
 use sqlx::{
-    Error, FromRow, Pool, Postgres, Row, query, query_as,
+    Error, FromRow, Pool, Postgres, query, query_as,
     types::JsonValue,
     types::chrono::{DateTime, NaiveDateTime, Utc},
 };
 use std::ops::Deref;
-use tracing::error;
 
 type Executor = Pool<Postgres>;
 type Result<T> = std::result::Result<T, Error>;
@@ -49,44 +49,159 @@ impl Model {
         Ok(v)
     }
 
-    pub async fn login(&self, token: &str) -> Result<(String, String)> {
-        let mut s = query(indoc! {
+    /// `None` when the name is taken.
+    pub async fn register(&self, name: &str, email: &str, hash: &str) -> Result<Option<Account>> {
+        query_as(indoc! {
+            "
+            insert into account (name, email, password, registered)
+            values ($1, $2, $3, true)
+            on conflict (name) do nothing
+            returning *
+            "
+        })
+        .bind(name)
+        .bind(email)
+        .bind(hash)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// A fresh unregistered account, it can only ever log in through its session.
+    pub async fn guest(&self, name: &str) -> Result<i32> {
+        let (id,) = query_as("insert into account (name, email) values ($1, '') returning id")
+            .bind(name)
+            .fetch_one(self.deref())
+            .await?;
+        Ok(id)
+    }
+
+    /// Id and password hash of the registered account named `login`, by name or email.
+    pub async fn password_of(&self, login: &str) -> Result<Option<(i32, Option<String>)>> {
+        query_as(indoc! {
+            "
+            select id, password from account
+            where (name = $1 or email = $1) and registered
+            limit 1
+            "
+        })
+        .bind(login)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Stores the SHA-256 of `token` for the registered account named `login`,
+    /// by name or email, and returns its name and email to deliver it to.
+    pub async fn issue_magic(&self, login: &str, token: &str) -> Result<Option<(String, String)>> {
+        query_as(indoc! {
             "
             with a as (
-                select a.id, a.name from account as a
-                left outer join session as s on a.id = s.account_id
-                where s.id is null
+                select id, name, email from account
+                where (name = $1 or email = $1) and registered
                 limit 1
             )
-            , s as (
-                insert into session (id, account_id)
-                select $1, id from a returning id
+            , t as (
+                insert into magic_token (token_hash, account_id)
+                select sha256(convert_to($2, 'UTF8')), id from a
             )
-            select a.name, s.id from a, s;
+            select name, email from a
             "
         })
+        .bind(login)
         .bind(token)
-        .fetch(self.deref());
-        if let Some(r) = s.try_next().await? {
-            let name: &str = r.try_get("name")?;
-            let id: &str = r.try_get("id")?;
-            Ok((id.to_string(), name.to_string()))
-        } else {
-            error!("insert session failed: {}", token);
-            Err(Error::RowNotFound)
-        }
+        .fetch_optional(self.deref())
+        .await
     }
 
-    pub async fn logout(&self, session_id: &str) -> Result<()> {
-        query(indoc! {
+    /// Marks an unused `token` younger than `ttl` seconds as used, and returns its account.
+    pub async fn redeem_magic(&self, token: &str, ttl: u64) -> Result<Option<i32>> {
+        let r: Option<(i32,)> = query_as(indoc! {
             "
-            delete from session where id = $1
+            update magic_token set used = now()
+            where token_hash = sha256(convert_to($1, 'UTF8')) and used is null
+            and created > now() - make_interval(secs => $2)
+            returning account_id
+            "
+        })
+        .bind(token)
+        .bind(ttl as f64)
+        .fetch_optional(self.deref())
+        .await?;
+        Ok(r.map(|x| x.0))
+    }
+
+    /// Returns the session id and the account name.
+    pub async fn open_session(
+        &self,
+        session_id: &str,
+        account_id: i32,
+    ) -> Result<(String, String)> {
+        query_as(indoc! {
+            "
+            with s as (
+                insert into session (id, account_id)
+                values ($1, $2) returning id, account_id
+            )
+            select s.id, a.name from s
+            join account as a on s.account_id = a.id
             "
         })
         .bind(session_id)
-        .execute(self.deref())
+        .bind(account_id)
+        .fetch_one(self.deref())
+        .await
+    }
+
+    /// Extends a session used within the last `ttl` seconds, `None` once it expired.
+    pub async fn resume_session(
+        &self,
+        session_id: &str,
+        ttl: u64,
+    ) -> Result<Option<(String, String)>> {
+        query_as(indoc! {
+            "
+            update session as s set updated = now()
+            from account as a
+            where s.id = $1 and s.account_id = a.id
+            and s.updated > now() - make_interval(secs => $2)
+            returning s.id, a.name
+            "
+        })
+        .bind(session_id)
+        .bind(ttl as f64)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Ends the session, and sweeps the expired sessions and magic tokens, and
+    /// the guest accounts left without a session or a message.
+    pub async fn logout(&self, session_id: &str, auth: &Auth) -> Result<()> {
+        let mut tx = self.begin().await?;
+        query("delete from session where id = $1 or updated < now() - make_interval(secs => $2)")
+            .bind(session_id)
+            .bind(auth.session_ttl as f64)
+            .execute(&mut *tx)
+            .await?;
+        query("delete from magic_token where used is not null or created < now() - make_interval(secs => $1)")
+            .bind(auth.magic_ttl as f64)
+            .execute(&mut *tx)
+            .await?;
+        query(indoc! {
+            "
+            with g as (
+                select a.id from account as a
+                where not a.registered and a.password is null and a.email = ''
+                and not exists (select 1 from session as s where s.account_id = a.id)
+                and not exists (select 1 from message as m where m.account_id = a.id)
+            )
+            , ca as (
+                delete from channel_account where account_id in (select id from g)
+            )
+            delete from account where id in (select id from g)
+            "
+        })
+        .execute(&mut *tx)
         .await?;
-        Ok(())
+        tx.commit().await
     }
 }
 
@@ -94,14 +209,63 @@ impl Model {
 pub struct Channel {
     pub id: i32,
     pub name: String,
+    pub parent_id: Option<i32>,
+    pub archived: bool,
 }
 
+/// In the requests below `channel` and `parent` are a channel name or id.
 #[derive(Debug, Deserialize)]
 pub struct CreateChan {
     pub session: String,
     pub name: String,
+    pub parent: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChanRef {
+    pub session: String,
+    pub channel: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameChan {
+    pub session: String,
+    pub channel: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveChan {
+    pub session: String,
+    pub channel: String,
+    /// `false` to restore the channel.
+    pub archived: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferChan {
+    pub session: String,
+    pub channel: String,
+    /// Name of the new owner, already a member.
+    pub account: String,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Member {
+    pub id: i32,
+    pub name: String,
+    pub owner: bool,
+}
+
+/// Channels of the session `$1` matching `$2`, with the membership of its account.
+const MEMBER_OF: &str = "
+    select c.id, ca.account_id, ca.owner from session as s
+    join channel_account as ca on ca.account_id = s.account_id
+    join channel as c on ca.channel_id = c.id
+    where s.id = $1 and (c.name = $2 or c.id::text = $2)
+    limit 1
+";
+
 #[derive(Debug, Deserialize)]
 pub struct JoinChan {
     pub session: String,
@@ -110,13 +274,26 @@ pub struct JoinChan {
 }
 
 impl Model {
+    /// Depth-first, each channel followed by its sub-channels, siblings by name.
+    /// A channel whose parent the session is not in starts a tree of its own.
     pub async fn list_channel(&self, session_id: &str) -> Result<Vec<Channel>> {
         query_as(indoc! {
             "
-            select c.id, c.name from channel as c
-            join channel_account as ca on ca.channel_id = c.id
-            join session as s on ca.account_id = s.account_id
-            where s.id = $1
+            with recursive m as (
+                select c.id, c.name, c.parent_id, c.archived from channel as c
+                join channel_account as ca on ca.channel_id = c.id
+                join session as s on ca.account_id = s.account_id
+                where s.id = $1
+            )
+            , t as (
+                select m.*, array[m.name::text, m.id::text] as path from m
+                where not exists (select 1 from m as p where p.id = m.parent_id)
+                union all
+                select m.*, t.path || array[m.name::text, m.id::text] from m
+                join t on m.parent_id = t.id
+            )
+            select id, name, parent_id, archived from t
+            order by path
             "
         })
         .bind(session_id)
@@ -124,14 +301,25 @@ impl Model {
         .await
     }
 
-    pub async fn create_channel(&self, arg: &CreateChan) -> Result<Channel> {
+    /// `None` when the session is unknown or not in the parent channel.
+    pub async fn create_channel(&self, arg: &CreateChan) -> Result<Option<Channel>> {
         query_as(indoc! {
             "
-            with x as (
-                insert into channel(name) values($1) returning id, name
+            with a as (
+                select account_id from session where id = $2 limit 1
             )
-            , a as (
-                select account_id from session where id = $2
+            , p as (
+                select c.id from channel as c
+                join channel_account as ca on ca.channel_id = c.id
+                join a on ca.account_id = a.account_id
+                where c.name = $3 or c.id::text = $3
+                limit 1
+            )
+            , x as (
+                insert into channel(name, parent_id)
+                select $1, (select id from p) from a
+                where $3::text is null or exists (select 1 from p)
+                returning id, name, parent_id, archived
             )
             , r as (
                 insert into channel_account(channel_id, account_id, owner)
@@ -142,7 +330,179 @@ impl Model {
         })
         .bind(&arg.name)
         .bind(&arg.session)
-        .fetch_one(self.deref())
+        .bind(&arg.parent)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Owner only.
+    pub async fn rename_channel(&self, arg: &RenameChan) -> Result<Option<Channel>> {
+        query_as(&format!(
+            "with m as ({MEMBER_OF})
+            update channel set name = $3 from m
+            where channel.id = m.id and m.owner
+            returning channel.id, channel.name, channel.parent_id, channel.archived"
+        ))
+        .bind(&arg.session)
+        .bind(&arg.channel)
+        .bind(&arg.name)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Owner only. An archived channel stays readable but takes no new messages.
+    pub async fn archive_channel(&self, arg: &ArchiveChan) -> Result<Option<Channel>> {
+        query_as(&format!(
+            "with m as ({MEMBER_OF})
+            update channel set archived = $3 from m
+            where channel.id = m.id and m.owner
+            returning channel.id, channel.name, channel.parent_id, channel.archived"
+        ))
+        .bind(&arg.session)
+        .bind(&arg.channel)
+        .bind(arg.archived.unwrap_or(true))
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Owner only, drops the messages and memberships, sub-channels move up
+    /// to the parent.
+    pub async fn delete_channel(&self, arg: &ChanRef) -> Result<bool> {
+        let mut tx = self.begin().await?;
+        let owned: Option<(i32, bool)> =
+            query_as(&format!("select m.id, m.owner from ({MEMBER_OF}) as m"))
+                .bind(&arg.session)
+                .bind(&arg.channel)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some((id, true)) = owned else {
+            return Ok(false);
+        };
+        for q in [
+            "update channel set parent_id = (select parent_id from channel where id = $1) where parent_id = $1",
+            "update session set channel_id = null where channel_id = $1",
+            "delete from message where channel_id = $1",
+            "delete from channel_summary where channel_id = $1",
+            "delete from channel_account where channel_id = $1",
+            "delete from channel where id = $1",
+        ] {
+            query(q).bind(id).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// The owner has to hand the channel over first, unless alone in it.
+    pub async fn leave_channel(&self, arg: &ChanRef) -> Result<bool> {
+        let mut tx = self.begin().await?;
+        let left: Option<(i32,)> = query_as(&format!(
+            "with m as ({MEMBER_OF})
+            delete from channel_account as ca using m
+            where ca.channel_id = m.id and ca.account_id = m.account_id
+            and (not m.owner or not exists (
+                select 1 from channel_account as o
+                where o.channel_id = m.id and o.account_id <> m.account_id
+            ))
+            returning ca.channel_id"
+        ))
+        .bind(&arg.session)
+        .bind(&arg.channel)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((id,)) = left else {
+            return Ok(false);
+        };
+        query("update session set channel_id = null where id = $1 and channel_id = $2")
+            .bind(&arg.session)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Members only.
+    pub async fn list_member(&self, arg: &ChanRef) -> Result<Vec<Member>> {
+        query_as(&format!(
+            "with m as ({MEMBER_OF})
+            select a.id, a.name, ca.owner from channel_account as ca
+            join m on ca.channel_id = m.id
+            join account as a on ca.account_id = a.id
+            order by ca.owner desc, a.name"
+        ))
+        .bind(&arg.session)
+        .bind(&arg.channel)
+        .fetch_all(self.deref())
+        .await
+    }
+
+    /// Owner only, to another member.
+    pub async fn transfer_channel(&self, arg: &TransferChan) -> Result<bool> {
+        let r = query(&format!(
+            "with m as ({MEMBER_OF})
+            , t as (
+                select ca.account_id from channel_account as ca
+                join m on ca.channel_id = m.id
+                join account as a on ca.account_id = a.id
+                where a.name = $3 and ca.account_id <> m.account_id
+            )
+            update channel_account as ca set owner = (ca.account_id = t.account_id)
+            from m, t
+            where m.owner and ca.channel_id = m.id
+            and ca.account_id in (m.account_id, t.account_id)"
+        ))
+        .bind(&arg.session)
+        .bind(&arg.channel)
+        .bind(&arg.account)
+        .execute(self.deref())
+        .await?;
+        Ok(r.rows_affected() == 2)
+    }
+
+    /// The channel named `channel` (name or id) among those of the session,
+    /// else its active channel, else its first one not archived. Archived
+    /// channels are returned too, for reading.
+    pub async fn current_channel(
+        &self,
+        session_id: &str,
+        channel: Option<&str>,
+    ) -> Result<Option<Channel>> {
+        query_as(indoc! {
+            "
+            select c.id, c.name, c.parent_id, c.archived from session as s
+            join channel_account as ca on ca.account_id = s.account_id
+            join channel as c on ca.channel_id = c.id
+            where s.id = $1 and ($2::text is null or c.name = $2 or c.id::text = $2)
+            order by c.id is not distinct from s.channel_id desc, c.archived, c.id
+            limit 1
+            "
+        })
+        .bind(session_id)
+        .bind(channel)
+        .fetch_optional(self.deref())
+        .await
+    }
+
+    /// Makes `channel` (name or id) the active channel of the session, if it
+    /// is a member.
+    pub async fn select_channel(&self, session_id: &str, channel: &str) -> Result<Option<Channel>> {
+        query_as(indoc! {
+            "
+            with c as (
+                select c.* from session as s
+                join channel_account as ca on ca.account_id = s.account_id
+                join channel as c on ca.channel_id = c.id
+                where s.id = $1 and (c.name = $2 or c.id::text = $2)
+                limit 1
+            )
+            update session set channel_id = c.id from c
+            where session.id = $1
+            returning c.id, c.name, c.parent_id, c.archived
+            "
+        })
+        .bind(session_id)
+        .bind(channel)
+        .fetch_optional(self.deref())
         .await
     }
 
@@ -175,6 +535,170 @@ impl Model {
     }
 }
 
+#[derive(Debug, Serialize, FromRow)]
+pub struct ChatRecord {
+    pub channel_id: i32,
+    pub sender: String,
+    pub created: NaiveDateTime,
+    pub content: JsonValue,
+}
+
+impl Model {
+    /// Stores `content` as JSON text, from the account behind `session_id`.
+    pub async fn save_message(
+        &self,
+        channel_id: i32,
+        session_id: &str,
+        content: &JsonValue,
+    ) -> Result<()> {
+        query(indoc! {
+            "
+            with s as (
+                update session set updated = now() where id = $2
+                returning account_id
+            )
+            insert into message (channel_id, account_id, content)
+            select $1, account_id, $3 from s
+            "
+        })
+        .bind(channel_id)
+        .bind(session_id)
+        .bind(content.to_string())
+        .execute(self.deref())
+        .await?;
+        Ok(())
+    }
+
+    /// Newest first, older pages by passing the `created` of the last one as `before`.
+    pub async fn list_message(
+        &self,
+        channel_id: i32,
+        before: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<ChatRecord>> {
+        query_as(indoc! {
+            "
+            select m.channel_id, coalesce(a.name, m.role) as sender, m.created,
+                m.content::jsonb as content
+            from message as m
+            left join account as a on m.account_id = a.id
+            where m.channel_id = $1 and ($2::timestamp is null or m.created < $2)
+            order by m.created desc
+            limit $3
+            "
+        })
+        .bind(channel_id)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(self.deref())
+        .await
+    }
+
+    /// Stores an answer of a model, which has no account.
+    pub async fn save_answer(&self, channel_id: i32, content: &JsonValue) -> Result<()> {
+        query(indoc! {
+            "
+            insert into message (channel_id, role, content)
+            values ($1, 'assistant', $2)
+            "
+        })
+        .bind(channel_id)
+        .bind(content.to_string())
+        .execute(self.deref())
+        .await?;
+        Ok(())
+    }
+
+    /// The newest `limit` messages after `since`, newest first.
+    pub async fn list_turn(
+        &self,
+        channel_id: i32,
+        since: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<TurnRecord>> {
+        query_as(indoc! {
+            "
+            select m.role, m.created, m.content::jsonb as content, a.name as sender
+            from message as m
+            left join account as a on a.id = m.account_id
+            where m.channel_id = $1 and ($2::timestamp is null or m.created > $2)
+            order by m.created desc
+            limit $3
+            "
+        })
+        .bind(channel_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(self.deref())
+        .await
+    }
+
+    /// The oldest `limit` messages after `since`, oldest first.
+    pub async fn page_turn(
+        &self,
+        channel_id: i32,
+        since: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<TurnRecord>> {
+        query_as(indoc! {
+            "
+            select m.role, m.created, m.content::jsonb as content, a.name as sender
+            from message as m
+            left join account as a on a.id = m.account_id
+            where m.channel_id = $1 and ($2::timestamp is null or m.created > $2)
+            order by m.created
+            limit $3
+            "
+        })
+        .bind(channel_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(self.deref())
+        .await
+    }
+
+    pub async fn channel_summary(
+        &self,
+        channel_id: i32,
+    ) -> Result<Option<(String, NaiveDateTime)>> {
+        query_as("select summary, upto from channel_summary where channel_id = $1")
+            .bind(channel_id)
+            .fetch_optional(self.deref())
+            .await
+    }
+
+    pub async fn save_summary(
+        &self,
+        channel_id: i32,
+        summary: &str,
+        upto: NaiveDateTime,
+    ) -> Result<()> {
+        query(indoc! {
+            "
+            insert into channel_summary (channel_id, summary, upto)
+            values ($1, $2, $3)
+            on conflict (channel_id) do update
+            set summary = excluded.summary, upto = excluded.upto, updated = now()
+            "
+        })
+        .bind(channel_id)
+        .bind(summary)
+        .bind(upto)
+        .execute(self.deref())
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TurnRecord {
+    pub role: String,
+    pub created: NaiveDateTime,
+    pub content: JsonValue,
+    /// Name of the account that wrote it, none for the model's answers.
+    pub sender: Option<String>,
+}
+
 #[derive(Debug, Serialize, FromRow)]
 pub struct ActiveUser {
     pub id: i32,
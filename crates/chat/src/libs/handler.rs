@@ -1,34 +1,330 @@
+use super::config::{LogicConf, OrderBy};
 use super::shared::Shared;
 use anyhow::Result;
+use brick::{Bind, BindVariant, Brick, Text, TextAttr};
+use content::{Content, Influx, Method};
+use futures::future::{BoxFuture, join_all};
+use maplit::hashmap;
 pub use message::{ChatMessage, Envelope};
+use message::{Event, glob};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
 use std::marker::Send;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::Duration;
 use tokio::sync::{
-    Mutex, RwLock,
-    mpsc::{UnboundedReceiver, UnboundedSender},
+    Mutex, RwLock, Semaphore,
+    mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    watch,
 };
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{Instrument, debug, error, info_span, warn};
 
 pub type Sender<T> = UnboundedSender<Envelope<T>>;
 pub type ArcShared = Arc<RwLock<Shared>>;
+/// The settings of the `[logic.<name>]` section a handler runs for.
+pub type Settings = Arc<Map<String, Value>>;
 
-pub async fn handler<T, F, Fut>(
+type Logic<T> = Arc<
+    dyn Fn(ChatMessage<T>, ArcShared, Sender<T>, Settings) -> BoxFuture<'static, Result<()>>
+        + Send
+        + Sync,
+>;
+
+struct Route<T> {
+    name: String,
+    conf: LogicConf,
+    settings: Settings,
+    logic: Logic<T>,
+}
+
+/// Handlers by event name glob. Every matching handler gets its own copy of
+/// a message, and each runs in its own task so a slow one does not hold up
+/// the others.
+pub struct Registry<T> {
+    routes: Vec<Route<T>>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+impl<T> Registry<T> {
+    pub fn register<F, Fut>(&mut self, name: &str, mut conf: LogicConf, f: F)
+    where
+        F: Fn(ChatMessage<T>, ArcShared, Sender<T>, Settings) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let settings = Arc::new(std::mem::take(&mut conf.settings));
+        self.routes.push(Route {
+            name: name.to_owned(),
+            conf,
+            settings,
+            logic: Arc::new(move |e, s, x, c| Box::pin(f(e, s, x, c))),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    fn matches(&self, event: &str) -> impl Iterator<Item = usize> {
+        self.routes.iter().enumerate().filter_map(move |(i, r)| {
+            r.conf
+                .event
+                .iter()
+                .any(|p| glob(p.as_bytes(), event.as_bytes()))
+                .then_some(i)
+        })
+    }
+}
+
+/// One route at work: at most `concurrency` messages at once, one at a time
+/// per key.
+struct Worker<T> {
+    name: String,
+    order: OrderBy,
+    timeout: Option<Duration>,
+    error_event: String,
+    limit: Semaphore,
+    settings: Settings,
+    logic: Logic<T>,
+    shared: ArcShared,
+    tx: Sender<T>,
+    /// Messages waiting behind the one in progress, by key.
+    pending: Queues<T>,
+    /// Messages waiting for the lookup of their channel key, by session.
+    resolving: Queues<T>,
+}
+
+type Queues<T> = SyncMutex<HashMap<String, VecDeque<ChatMessage<T>>>>;
+
+/// Queues `x` behind the messages of its key, or hands it back when none
+/// is in progress and the caller has to start with it.
+fn enqueue<T>(queues: &Queues<T>, key: &str, x: ChatMessage<T>) -> Option<ChatMessage<T>> {
+    let mut queues = queues.lock().unwrap();
+    match queues.get_mut(key) {
+        Some(q) => {
+            q.push_back(x);
+            None
+        }
+        None => {
+            queues.insert(key.to_owned(), VecDeque::new());
+            Some(x)
+        }
+    }
+}
+
+/// The next message of `key`, none left and `key` is free again.
+fn dequeue<T>(queues: &Queues<T>, key: &str) -> Option<ChatMessage<T>> {
+    let mut queues = queues.lock().unwrap();
+    let next = queues.get_mut(key).and_then(VecDeque::pop_front);
+    if next.is_none() {
+        queues.remove(key);
+    }
+    next
+}
+
+impl<T> Worker<T>
+where
+    T: Default + Send + Sync + 'static,
+{
+    /// The channel of `x`, looked up for `OrderBy::Channel`.
+    async fn channel_key(&self, x: &ChatMessage<T>) -> String {
+        // The UI names no channel, the active one of the session is meant
+        let db = self.shared.read().await.db.clone();
+        match db
+            .current_channel((&x.sender).into(), x.channel.as_deref())
+            .await
+        {
+            Ok(Some(c)) => return format!("#{}", c.id),
+            Ok(None) => {}
+            Err(e) => warn!("{}: channel of {}: {}", self.name, x.sender, e),
+        }
+        match &x.channel {
+            Some(channel) => format!("#{channel}"),
+            None => x.sender.to_string(),
+        }
+    }
+
+    /// Looks the channels of a session's messages up one after the other,
+    /// so they are queued in order while other sessions go on.
+    async fn resolve(self: Arc<Self>, session: String, mut x: ChatMessage<T>) {
+        let mut drains = JoinSet::new();
+        loop {
+            let key = self.channel_key(&x).await;
+            if let Some(x) = enqueue(&self.pending, &key, x) {
+                drains.spawn(self.clone().drain(key, x));
+            }
+            match dequeue(&self.resolving, &session) {
+                Some(next) => x = next,
+                None => break,
+            }
+        }
+        while drains.join_next().await.is_some() {}
+    }
+
+    async fn drain(self: Arc<Self>, key: String, mut x: ChatMessage<T>) {
+        loop {
+            self.run(x).await;
+            match dequeue(&self.pending, &key) {
+                Some(next) => x = next,
+                None => return,
+            }
+        }
+    }
+
+    async fn run(&self, x: ChatMessage<T>) {
+        // Never closed
+        let Ok(_permit) = self.limit.acquire().await else {
+            return;
+        };
+        let sender = x.sender.clone();
+        // Replies built inside the span inherit its trace context
+        let span =
+            info_span!("chat.handle", logic = %self.name, sender = %sender, event = x.event());
+        if let Some(t) = &x.trace {
+            t.attach(&span);
+        }
+        async {
+            let f = (self.logic)(
+                x,
+                self.shared.clone(),
+                self.tx.clone(),
+                self.settings.clone(),
+            );
+            let r = match self.timeout {
+                Some(t) => tokio::time::timeout(t, f).await,
+                None => Ok(f.await),
+            };
+            let fault = match r {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => {
+                    // The details stay in the log
+                    error!("{}: {:#}", self.name, e);
+                    format!("{} could not handle the message", self.name)
+                }
+                Err(_) => {
+                    warn!("{}: timed out after {:?}", self.name, self.timeout);
+                    format!("{} did not answer in time", self.name)
+                }
+            };
+            let content = Content::Join(Influx {
+                event: self.error_event.clone(),
+                channel: None,
+                method: Method::Concat,
+                data: Brick::text(Text {
+                    attrs: Some(TextAttr {
+                        class: Some(vec!["error".to_string()]),
+                        ..Default::default()
+                    }),
+                    bind: Some(hashmap! {
+                        "value".to_owned() => Bind {
+                            variant: BindVariant::Default {},
+                            default: Some(fault.into()),
+                            ..Default::default()
+                        }
+                    }),
+                    ..Default::default()
+                }),
+            });
+            if let Ok(content) = serde_json::to_value(content) {
+                let _ = self.tx.send(Envelope {
+                    receiver: vec![sender],
+                    message: (self.name.as_str().into(), content).into(),
+                });
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+pub async fn handler<T>(
     tx: Sender<T>,
     rx: Arc<Mutex<UnboundedReceiver<ChatMessage<T>>>>,
     shared: Shared,
-    mut f: F,
-) -> Result<()>
+    mut shutdown: watch::Receiver<bool>,
+    registry: Registry<T>,
+) -> Result<JoinHandle<()>>
 where
-    T: Send + 'static,
-    F: FnMut(ChatMessage<T>, ArcShared, Sender<T>) -> Fut + Clone + Send + 'static,
-    Fut: Future<Output = Result<()>> + Send,
+    T: Clone + Default + Send + Sync + 'static,
 {
     let shared = Arc::new(RwLock::new(shared));
 
-    tokio::spawn(async move {
+    let mut inbox = Vec::new();
+    let mut tasks = Vec::new();
+    for route in &registry.routes {
+        let (route_tx, mut route_rx) = unbounded_channel::<ChatMessage<T>>();
+        let worker = Arc::new(Worker {
+            name: route.name.clone(),
+            order: route.conf.order,
+            timeout: (route.conf.timeout > 0).then(|| Duration::from_secs(route.conf.timeout)),
+            error_event: route.conf.error_event.clone(),
+            limit: Semaphore::new(route.conf.concurrency.max(1)),
+            settings: route.settings.clone(),
+            logic: route.logic.clone(),
+            shared: shared.clone(),
+            tx: tx.clone(),
+            pending: SyncMutex::new(HashMap::new()),
+            resolving: SyncMutex::new(HashMap::new()),
+        });
+        inbox.push(route_tx);
+        tasks.push(tokio::spawn(async move {
+            let mut running = JoinSet::new();
+            loop {
+                tokio::select! {
+                    x = route_rx.recv() => {
+                        let Some(x) = x else { break };
+                        let session = x.sender.to_string();
+                        // No lookup here, a slow one would hold up every session
+                        if let OrderBy::Channel = worker.order {
+                            if let Some(x) = enqueue(&worker.resolving, &session, x) {
+                                running.spawn(worker.clone().resolve(session, x));
+                            }
+                        } else if let Some(x) = enqueue(&worker.pending, &session, x) {
+                            running.spawn(worker.clone().drain(session, x));
+                        }
+                    }
+                    Some(_) = running.join_next(), if !running.is_empty() => {}
+                }
+            }
+            while running.join_next().await.is_some() {}
+        }));
+    }
+    // Only the workers hold `tx` from here, once they are done the outgo queue drains
+    drop(tx);
+
+    let task = tokio::spawn(async move {
         let mut rx = rx.lock().await;
-        while let Some(x) = rx.recv().await {
-            f(x, shared.clone(), tx.clone()).await;
+        loop {
+            tokio::select! {
+                x = rx.recv() => match x {
+                    Some(x) => {
+                        let event = x.event().unwrap_or_default().to_owned();
+                        let mut routes = registry.matches(&event).peekable();
+                        if routes.peek().is_none() {
+                            debug!("no logic for {:?}", event);
+                        }
+                        for i in routes {
+                            let _ = inbox[i].send(x.clone());
+                        }
+                    }
+                    None => break,
+                },
+                // The watch guard is not `Send`, drop it inside the branch future
+                _ = async { shutdown.wait_for(|x| *x).await.is_ok() } => break,
+            }
         }
+        // Workers finish what they already got
+        drop(inbox);
+        join_all(tasks).await;
     });
-    Ok(())
+    Ok(task)
 }
+
+#[cfg(test)]
+#[path = "handler_test.rs"]
+mod tests;
@@ -0,0 +1,192 @@
+use super::super::config::Auth;
+use super::super::db::Model;
+use super::super::logic::echo;
+use super::*;
+use message::record::{Direction, VOLATILE, diff, load, messages};
+use message::session::Session;
+use message::time::Created;
+use serde_json::json;
+use sqlx::postgres::PgPoolOptions;
+
+type Log = Arc<SyncMutex<Vec<String>>>;
+
+/// No database behind it, channel lookups fail fast.
+fn shared() -> Shared {
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(50))
+        .connect_lazy("postgres://127.0.0.1:1/none")
+        .unwrap();
+    Shared::new(Model(pool), Auth::default())
+}
+
+fn conf(order: OrderBy, concurrency: usize, timeout: u64) -> LogicConf {
+    LogicConf {
+        order,
+        concurrency,
+        timeout,
+        ..Default::default()
+    }
+}
+
+/// From `sender`, taking `ms` to handle.
+fn msg(sender: &str, ms: u64, channel: Option<&str>) -> ChatMessage<()> {
+    let mut x: ChatMessage<()> = (sender.into(), json!({ "event": "slow", "data": ms })).into();
+    x.channel = channel.map(str::to_owned);
+    x
+}
+
+/// Sleeps the `data` of the message, noting when it starts and ends. The
+/// tests run on a paused clock, sleeps end in the order they are due.
+fn slow(conf: LogicConf, log: &Log) -> Registry<()> {
+    let log = log.clone();
+    let mut registry = Registry::default();
+    registry.register("slow", conf, move |x: ChatMessage<()>, _, _, _| {
+        let log = log.clone();
+        async move {
+            let ms = x.content["data"].as_u64().unwrap_or_default();
+            let tag = format!("{}{}", *x.sender, ms);
+            log.lock().unwrap().push(format!("start {tag}"));
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            log.lock().unwrap().push(format!("end {tag}"));
+            if ms == 0 {
+                anyhow::bail!("secret detail");
+            }
+            Ok(())
+        }
+    });
+    registry
+}
+
+/// Runs the registry over `xs` until everything is handled.
+async fn run<T>(registry: Registry<T>, xs: Vec<ChatMessage<T>>) -> Vec<Envelope<T>>
+where
+    T: Clone + Default + Send + Sync + 'static,
+{
+    let (tx, mut out) = unbounded_channel();
+    let (income_tx, income_rx) = unbounded_channel();
+    let (_stop, shutdown) = watch::channel(false);
+    let task = handler(
+        tx,
+        Arc::new(Mutex::new(income_rx)),
+        shared(),
+        shutdown,
+        registry,
+    )
+    .await
+    .unwrap();
+    for x in xs {
+        income_tx.send(x).unwrap();
+    }
+    drop(income_tx);
+    task.await.unwrap();
+    let mut v = Vec::new();
+    while let Ok(x) = out.try_recv() {
+        v.push(x);
+    }
+    v
+}
+
+fn at(log: &Log, line: &str) -> usize {
+    let log = log.lock().unwrap();
+    log.iter()
+        .position(|x| x == line)
+        .unwrap_or_else(|| panic!("{line} not in {log:?}"))
+}
+
+/// Most messages in progress at once.
+fn peak(log: &Log) -> usize {
+    let (mut now, mut peak) = (0, 0);
+    for x in log.lock().unwrap().iter() {
+        if x.starts_with("start") {
+            now += 1;
+            peak = peak.max(now);
+        } else {
+            now -= 1;
+        }
+    }
+    peak
+}
+
+#[tokio::test(start_paused = true)]
+async fn in_order_per_session() {
+    let log = Log::default();
+    let xs = vec![msg("a", 60, None), msg("a", 5, None), msg("b", 5, None)];
+    run(slow(conf(OrderBy::Session, 4, 0), &log), xs).await;
+    assert!(at(&log, "end a60") < at(&log, "start a5"));
+    // Another session does not wait
+    assert!(at(&log, "end b5") < at(&log, "end a60"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn in_order_per_channel() {
+    let log = Log::default();
+    let xs = vec![
+        msg("a", 300, Some("x")),
+        msg("a", 20, Some("x")),
+        msg("b", 5, Some("x")),
+        msg("c", 5, Some("y")),
+    ];
+    // Looking the channels up fails, the named ones are used
+    run(slow(conf(OrderBy::Channel, 4, 0), &log), xs).await;
+    assert!(at(&log, "end a300") < at(&log, "start a20"));
+    // Sessions are looked up side by side, whichever comes first in a channel
+    // has it to itself
+    assert!(
+        at(&log, "end b5") < at(&log, "start a300") || at(&log, "end a300") < at(&log, "start b5")
+    );
+    assert!(at(&log, "end c5") < at(&log, "end a300"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn bounded_concurrency() {
+    let log = Log::default();
+    let xs = ["a", "b", "c", "d", "e"]
+        .into_iter()
+        .map(|s| msg(s, 30, None))
+        .collect();
+    run(slow(conf(OrderBy::Session, 2, 0), &log), xs).await;
+    assert_eq!(log.lock().unwrap().len(), 10);
+    assert_eq!(peak(&log), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn timeout_and_failure_reported() {
+    let log = Log::default();
+    let xs = vec![msg("a", 1500, None), msg("b", 0, None)];
+    let out = run(slow(conf(OrderBy::Session, 4, 1), &log), xs).await;
+    assert!(!log.lock().unwrap().contains(&"end a1500".to_owned()));
+    assert_eq!(out.len(), 2);
+    let text = |sender: &str| {
+        let x = out
+            .iter()
+            .find(|x| x.receiver == vec![Session::from(sender)])
+            .unwrap();
+        x.message.content.to_string()
+    };
+    assert!(text("a").contains("did not answer in time"));
+    let fault = text("b");
+    assert!(fault.contains("could not handle the message"));
+    assert!(!fault.contains("secret"), "{fault}");
+}
+
+/// A recorded `echo` session, its inbound messages fed to the handler.
+#[tokio::test]
+async fn replay_echo() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../data/replay/echo.jsonl");
+    let entries = load(path).unwrap();
+    let xs: Vec<ChatMessage<Created>> = messages(&entries, Direction::Inbound)
+        .into_iter()
+        .map(|x| serde_json::from_value(x).unwrap())
+        .collect();
+    let mut registry = Registry::default();
+    registry.register("message", LogicConf::default(), echo);
+    let actual: Vec<_> = run(registry, xs)
+        .await
+        .into_iter()
+        .map(|x| serde_json::to_value(x.message).unwrap())
+        .collect();
+    let expected = messages(&entries, Direction::Outbound);
+    let ignore = VOLATILE.map(str::to_owned);
+    let mismatches = diff(&expected, &actual, &ignore);
+    assert!(mismatches.is_empty(), "{mismatches:#?}");
+}
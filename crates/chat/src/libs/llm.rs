@@ -0,0 +1,188 @@
+//! OpenAI-compatible chat completions. The answer streams out as `Concat`
+//! joins on a single brick id, which the UI merges into a typing effect.
+use super::handler::{Envelope, Sender};
+use agent::Model;
+use anyhow::{Result, anyhow, bail};
+use brick::{Bind, BindVariant, Brick, Text, TextAttr};
+use content::{Content, Influx, Method};
+use futures::{
+    TryStreamExt,
+    stream::{self, BoxStream, StreamExt},
+};
+use maplit::hashmap;
+use message::session::Session;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Turn {
+    pub role: String,
+    pub content: String,
+}
+
+impl Turn {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_owned(),
+            content: content.into(),
+        }
+    }
+}
+
+/// The `llm` table of a logic section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Llm {
+    /// `base_url`, `model`, `api_key`, `api_key_env` and `temperature`.
+    #[serde(flatten)]
+    pub model: Model,
+    pub system: Option<String>,
+    /// Asks for a JSON object (`response_format`), where the server supports it.
+    #[serde(default)]
+    pub json: bool,
+}
+
+impl Llm {
+    /// The content deltas of the completion of `messages`.
+    pub async fn stream(&self, messages: &[Turn]) -> Result<BoxStream<'static, Result<String>>> {
+        let mut body = Map::new();
+        body.insert("stream".into(), true.into());
+        let system = self.system.iter().map(|x| Turn::new("system", x));
+        body.insert(
+            "messages".into(),
+            serde_json::to_value(system.chain(messages.iter().cloned()).collect::<Vec<_>>())?,
+        );
+        if self.json {
+            body.insert("response_format".into(), json!({"type": "json_object"}));
+        }
+        let resp = self.model.request(body).await?;
+        Ok(sse(resp).boxed())
+    }
+
+    /// The whole completion of `messages`.
+    pub async fn complete(&self, messages: &[Turn]) -> Result<String> {
+        let mut deltas = self.stream(messages).await?;
+        let mut answer = String::new();
+        while let Some(delta) = deltas.try_next().await? {
+            answer.push_str(&delta);
+        }
+        Ok(answer)
+    }
+}
+
+struct Events {
+    resp: Response,
+    buf: Vec<u8>,
+    ready: VecDeque<String>,
+    done: bool,
+}
+
+/// `choices[0].delta.content` of every `data:` event, until `[DONE]`.
+fn sse(resp: Response) -> impl futures::Stream<Item = Result<String>> {
+    let s = Events {
+        resp,
+        buf: Vec::new(),
+        ready: VecDeque::new(),
+        done: false,
+    };
+    stream::try_unfold(s, |mut s| async move {
+        loop {
+            if let Some(x) = s.ready.pop_front() {
+                return Ok(Some((x, s)));
+            }
+            if s.done {
+                return Ok(None);
+            }
+            match s.resp.chunk().await? {
+                Some(chunk) => s.buf.extend(chunk.iter().filter(|b| **b != b'\r')),
+                // A last event without its blank line
+                None => {
+                    s.buf.extend_from_slice(b"\n\n");
+                    s.done = true;
+                }
+            }
+            s.done |= parse(&mut s.buf, &mut s.ready)?;
+        }
+    })
+}
+
+/// Takes the complete events off `buf`, `true` once `[DONE]` came.
+fn parse(buf: &mut Vec<u8>, ready: &mut VecDeque<String>) -> Result<bool> {
+    while let Some(end) = buf.windows(2).position(|x| x == b"\n\n") {
+        let event: Vec<u8> = buf.drain(..end + 2).collect();
+        for line in String::from_utf8_lossy(&event).lines() {
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                return Ok(true);
+            }
+            let v: Value = serde_json::from_str(data)?;
+            if let Some(e) = v.get("error") {
+                bail!("{}", e);
+            }
+            if let Some(x) = v
+                .pointer("/choices/0/delta/content")
+                .and_then(Value::as_str)
+                .filter(|x| !x.is_empty())
+            {
+                ready.push_back(x.to_owned());
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// One delta of the answer `id`.
+pub fn answer_brick(id: &str, event: &str, channel: Option<&str>, delta: &str) -> Content<Brick> {
+    Content::Join(Influx {
+        event: event.into(),
+        channel: channel.map(str::to_owned),
+        data: Brick::text(Text {
+            id: Some(id.to_owned()),
+            attrs: Some(TextAttr {
+                format: Some("md".to_string()),
+                selector: Some("answer".to_string()),
+                ..Default::default()
+            }),
+            bind: Some(hashmap! {
+                "value".to_owned() => Bind {
+                    variant: BindVariant::Default {},
+                    default: Some(json!(delta)),
+                    ..Default::default()
+                }
+            }),
+        }),
+        method: Method::Concat,
+    })
+}
+
+/// Streams the answer to `messages` to every receiver as it comes, sent by
+/// the model, and returns it whole.
+pub async fn reply<T: Default>(
+    llm: &Llm,
+    messages: &[Turn],
+    id: &str,
+    event: &str,
+    channel: Option<&str>,
+    receiver: &[Session],
+    tx: &Sender<T>,
+) -> Result<String> {
+    let mut deltas = llm.stream(messages).await?;
+    let mut answer = String::new();
+    while let Some(delta) = deltas.try_next().await? {
+        let content = serde_json::to_value(answer_brick(id, event, channel, &delta))?;
+        tx.send(Envelope {
+            receiver: receiver.to_vec(),
+            message: (llm.model.model.as_str().into(), content).into(),
+        })
+        .map_err(|_| anyhow!("outgo queue closed"))?;
+        answer.push_str(&delta);
+    }
+    Ok(answer)
+}
+
+#[cfg(test)]
+#[path = "llm_test.rs"]
+mod tests;
@@ -0,0 +1,102 @@
+use super::*;
+use axum::{Router, body::Body, routing::post};
+use brick::BrickOps;
+use std::convert::Infallible;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// Replays `chunks` as the body of every completion request.
+async fn mock(status: u16, chunks: &'static [&'static str]) -> Llm {
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move || async move {
+            let body = stream::iter(chunks.iter().map(|x| Ok::<_, Infallible>(*x)));
+            axum::http::Response::builder()
+                .status(status)
+                .header("content-type", "text/event-stream")
+                .body(Body::from_stream(body))
+                .unwrap()
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Llm {
+        model: Model {
+            base_url: format!("http://{addr}/v1"),
+            model: "mock".into(),
+            api_key: None,
+            api_key_env: "MOCK_API_KEY_UNSET".into(),
+            temperature: None,
+        },
+        system: Some("be brief".into()),
+        json: false,
+    }
+}
+
+/// Events split across chunks, CRLF line ends and a role-only first delta.
+const CHUNKS: &[&str] = &[
+    "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+    "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\r\n\r\ndata: {\"choi",
+    "ces\":[{\"delta\":{\"content\":\"lo, \"}}]}\n\n: keep-alive\n\n",
+    "data: {\"choices\":[{\"delta\":{\"content\":\"wörld\"}}]}\n\n",
+    "data: [DONE]\n\n",
+];
+
+#[tokio::test]
+async fn stream_deltas() {
+    let llm = mock(200, CHUNKS).await;
+    let deltas: Vec<String> = llm
+        .stream(&[Turn::new("user", "hi")])
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(deltas, ["Hel", "lo, ", "wörld"]);
+}
+
+#[tokio::test]
+async fn reply_concat_on_one_brick() {
+    let llm = mock(200, CHUNKS).await;
+    let (tx, mut rx) = unbounded_channel::<Envelope<()>>();
+    let receiver: Vec<Session> = vec!["a".into(), "b".into()];
+    let answer = reply(
+        &llm,
+        &[Turn::new("user", "hi")],
+        "x1",
+        "chat",
+        Some("general"),
+        &receiver,
+        &tx,
+    )
+    .await
+    .unwrap();
+    assert_eq!(answer, "Hello, wörld");
+
+    drop(tx);
+    let mut merged = String::new();
+    while let Some(e) = rx.recv().await {
+        assert_eq!(e.receiver, receiver);
+        let Content::Join(x) = serde_json::from_value::<Content<Brick>>(e.message.content).unwrap()
+        else {
+            panic!("not a join");
+        };
+        assert_eq!(x.method, Method::Concat);
+        assert_eq!(x.channel.as_deref(), Some("general"));
+        assert_eq!(x.data.get_id().as_deref(), Some("x1"));
+        let value = x
+            .data
+            .get_bind()
+            .and_then(|b| b.get("value")?.default.clone());
+        merged.push_str(value.as_ref().and_then(Value::as_str).unwrap());
+    }
+    assert_eq!(merged, answer);
+}
+
+#[tokio::test]
+async fn error_status() {
+    let llm = mock(429, &["{\"error\":{\"message\":\"slow down\"}}"]).await;
+    let e = llm.stream(&[Turn::new("user", "hi")]).await.err().unwrap();
+    assert!(e.to_string().contains("slow down"), "{e}");
+}
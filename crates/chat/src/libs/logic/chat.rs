@@ -1,55 +1,137 @@
-use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender};
+use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender, Settings};
+use super::super::llm::{Llm, Turn, reply};
+use super::super::memory::{Memory, Window, context, signed};
 use anyhow::Result;
 use brick::{Bind, BindVariant, Brick, Text, TextAttr};
-use content::{Content, Influx, Method};
+use content::{Content, Fault, Influx, Method};
 use maplit::hashmap;
+use message::session::Session;
+use serde_json::Value;
+use short_uuid::ShortUuid;
 use std::default::Default;
 use std::fmt::Debug;
+use tracing::{error, warn};
 
-pub async fn chat<T: Debug + Default>(e: ChatMessage<T>, s: ArcShared, x: Sender<T>) -> Result<()> {
+/// How a chat message is shown, live and in the history.
+pub fn message_brick(d: &Value, channel: &str) -> Content<Brick> {
+    Content::Join(Influx {
+        event: "chat".into(),
+        channel: Some(channel.to_owned()),
+        data: Brick::text(Text {
+            attrs: Some(TextAttr {
+                format: Some("md".to_string()),
+                selector: Some("ask".to_string()),
+                ..Default::default()
+            }),
+            bind: Some(hashmap! {
+                "value".to_owned() => Bind {
+                    variant: BindVariant::Default {},
+                    default: Some(d.to_owned()),
+                    ..Default::default()
+                }
+            }),
+            ..Default::default()
+        }),
+        method: Method::Concat,
+    })
+}
+
+pub async fn chat<T: Debug + Default>(
+    e: ChatMessage<T>,
+    s: ArcShared,
+    x: Sender<T>,
+    settings: Settings,
+) -> Result<()> {
     let ChatMessage {
         sender,
-        // TODO: channel_id,
         created: _,
         content,
+        trace: _,
+        version: _,
+        channel,
     } = &e;
 
-    let s = s.read().await;
-    // TODO: channel id
-    let users = s.db.list_channel_account(3).await;
+    // Not held over the answer of the model
+    let db = s.read().await.db.clone();
+    let Some(chan) = db
+        .current_channel(sender.into(), channel.as_deref())
+        .await?
+    else {
+        warn!("{} is not in channel {:?}", sender, channel);
+        return Ok(());
+    };
+    // Still readable, but takes no new messages
+    if chan.archived {
+        let fault = Content::<Brick>::Error(Fault {
+            code: "archived".into(),
+            message: format!("channel {} is archived", chan.name),
+        });
+        if let Ok(content) = serde_json::to_value(fault) {
+            let _ = x.send(Envelope {
+                receiver: vec![sender.clone()],
+                message: ("chat".into(), content).into(),
+            });
+        }
+        return Ok(());
+    }
 
     if let Some(content) = content.as_object()
         && let Some(e) = content.get("event")
         && let Some(_event) = e.as_str()
         && let Some(d) = content.get("data")
     {
-        let content = Content::Join(Influx {
-            event: "chat".into(),
-            channel: None,
-            data: Brick::text(Text {
-                attrs: Some(TextAttr {
-                    format: Some("md".to_string()),
-                    selector: Some("ask".to_string()),
-                    ..Default::default()
-                }),
-                bind: Some(hashmap! {
-                    "value".to_owned() => Bind {
-                        variant: BindVariant::Default {},
-                        default: Some(d.to_owned()),
-                        ..Default::default()
-                    }
-                }),
-                ..Default::default()
-            }),
-            method: Method::Concat,
-        });
-        if let Ok(content) = serde_json::to_value(content) {
+        if let Err(e) = db.save_message(chan.id, sender.into(), d).await {
+            error!("save message from {}: {}", sender, e);
+        }
+        // Every session of every member, the sender included
+        let members = db.list_channel_account(chan.id).await?;
+        let receiver: Vec<Session> = members
+            .iter()
+            .map(|u| u.session_id.as_str().into())
+            .collect();
+        if let Ok(content) = serde_json::to_value(message_brick(d, &chan.name)) {
             let cm: ChatMessage<T> = ("chat".into(), content).into();
             let _ = x.send(Envelope {
-                receiver: vec![sender.clone()],
+                receiver: receiver.clone(),
                 message: cm,
             });
         }
+
+        // The model answers in the channel when the section has an `llm` table,
+        // told what the `memory` table lets through of the channel
+        if let Some(llm) = settings.get("llm") {
+            let llm: Llm = serde_json::from_value(llm.clone())?;
+            let window: Window = match settings.get("memory") {
+                Some(x) => serde_json::from_value(x.clone())?,
+                None => Window::default(),
+            };
+            let mut messages =
+                context(&db, chan.id, &window, window.summarize.then_some(&llm)).await?;
+            // Not saved above
+            if messages.is_empty() {
+                let ask = match d {
+                    Value::String(x) => x.clone(),
+                    x => x.to_string(),
+                };
+                let name = members.iter().find(|u| u.session_id == **sender);
+                messages.push(Turn::new(
+                    "user",
+                    signed(name.map(|u| u.name.as_str()), ask),
+                ));
+            }
+            let id = ShortUuid::generate().to_string();
+            let answer = reply(
+                &llm,
+                &messages,
+                &id,
+                "chat",
+                Some(&chan.name),
+                &receiver,
+                &x,
+            )
+            .await?;
+            db.answer(chan.id, &answer).await?;
+        }
     };
     Ok(())
 }
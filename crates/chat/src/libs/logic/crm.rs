@@ -1,10 +1,15 @@
-use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender};
+use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender, Settings};
 use anyhow::Result;
 use std::fmt::Debug;
 
 const _BRAND: &str = "kairos开若";
 
-pub async fn crm<T: Debug>(e: ChatMessage<T>, s: ArcShared, x: Sender<T>) -> Result<()> {
+pub async fn crm<T: Debug>(
+    e: ChatMessage<T>,
+    s: ArcShared,
+    x: Sender<T>,
+    _: Settings,
+) -> Result<()> {
     println!("crm => {:?}", e);
     Ok(())
 }
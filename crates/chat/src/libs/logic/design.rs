@@ -0,0 +1,58 @@
+use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender, Settings};
+use super::super::llm::Llm;
+use super::super::structured::generate;
+use anyhow::{Result, anyhow};
+use content::{Content, Influx, Method};
+use message::Event;
+use serde_json::Value;
+use std::fmt::Debug;
+
+/// Answers with the interface the message describes, drawn by the model of
+/// the `llm` table. It replaces the layout, or joins the list of the event
+/// set as `join`. `retries` bounds the attempts at a valid tree.
+pub async fn design<T: Debug + Default>(
+    e: ChatMessage<T>,
+    _: ArcShared,
+    x: Sender<T>,
+    settings: Settings,
+) -> Result<()> {
+    let llm = settings
+        .get("llm")
+        .ok_or_else(|| anyhow!("no `llm` table"))?;
+    let llm: Llm = serde_json::from_value(llm.clone())?;
+    let retries = settings.get("retries").and_then(Value::as_u64).unwrap_or(2);
+
+    let Some(d) = e.content.get("data") else {
+        return Ok(());
+    };
+    let ask = match d {
+        Value::String(x) => x.clone(),
+        x => x.to_string(),
+    };
+    let brick = generate(&llm, &ask, retries as usize).await?;
+
+    let content = match settings.get("join").and_then(Value::as_str) {
+        Some(event) => Content::Join(Influx {
+            event: event.into(),
+            channel: None,
+            method: Method::Replace,
+            data: brick,
+        }),
+        None => Content::Create(Influx {
+            event: e.event().unwrap_or_default().into(),
+            channel: None,
+            method: Method::Replace,
+            data: brick,
+        }),
+    };
+    let cm: ChatMessage<T> = (
+        llm.model.model.as_str().into(),
+        serde_json::to_value(content)?,
+    )
+        .into();
+    let _ = x.send(Envelope {
+        receiver: vec![e.sender],
+        message: cm,
+    });
+    Ok(())
+}
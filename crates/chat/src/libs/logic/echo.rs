@@ -1,4 +1,4 @@
-use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender};
+use super::super::handler::{ArcShared, ChatMessage, Envelope, Sender, Settings};
 use anyhow::Result;
 use brick::{Bind, Brick, Text, TextAttr};
 use content::{Content, Influx, Method};
@@ -6,18 +6,31 @@ use maplit::hashmap;
 use std::default::Default;
 use std::fmt::Debug;
 
-pub async fn echo<T: Debug + Default>(e: ChatMessage<T>, s: ArcShared, x: Sender<T>) -> Result<()> {
+/// Sends `data` back to the sender, on the event set as `reply` (`chat` by default).
+pub async fn echo<T: Debug + Default>(
+    e: ChatMessage<T>,
+    s: ArcShared,
+    x: Sender<T>,
+    settings: Settings,
+) -> Result<()> {
+    let reply = settings
+        .get("reply")
+        .and_then(|x| x.as_str())
+        .unwrap_or("chat");
     let ChatMessage {
         sender,
         created: _,
         content,
+        trace: _,
+        version: _,
+        channel: _,
     } = &e;
 
     if let Some(content) = content.as_object()
         && let Some(d) = content.get("data")
     {
         let content = Content::Join(Influx {
-            event: "chat".into(),
+            event: reply.into(),
             channel: None,
             data: Brick::text(Text {
                 id: None,
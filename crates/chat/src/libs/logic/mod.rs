@@ -1,6 +1,39 @@
 mod chat;
-pub use chat::chat;
+pub use chat::{chat, message_brick};
 mod crm;
 pub use crm::crm;
+mod design;
+pub use design::design;
 mod echo;
 pub use echo::echo;
+
+use super::config::{Logic, Logics};
+use super::handler::Registry;
+use anyhow::Result;
+use std::fmt::Debug;
+use tracing::info;
+
+/// The enabled `[logic.<name>]` sections, by event glob.
+pub fn registry<T>(logic: Logics) -> Result<Registry<T>>
+where
+    T: Debug + Default + Send + Sync + 'static,
+{
+    let mut registry = Registry::default();
+    for (name, conf) in logic.sections() {
+        if conf.disable {
+            continue;
+        }
+        let kind = conf.kind(&name)?;
+        info!(
+            "logic {}: {:?} on {:?}, {} at once by {:?}",
+            name, kind, conf.event, conf.concurrency, conf.order
+        );
+        match kind {
+            Logic::Chat => registry.register(&name, conf, chat),
+            Logic::Crm => registry.register(&name, conf, crm),
+            Logic::Echo => registry.register(&name, conf, echo),
+            Logic::Design => registry.register(&name, conf, design),
+        }
+    }
+    Ok(registry)
+}
@@ -0,0 +1,366 @@
+//! What a model is told of a channel: its newest messages within a token
+//! budget, those before folded into a summary kept per channel.
+use super::db::{Model, TurnRecord};
+use super::llm::{Llm, Turn};
+use anyhow::Result;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::warn;
+
+const SUMMARIZE: &str = "You keep the memory of a conversation. Extend the summary so far with \
+the messages given, keeping names, facts, decisions and open questions. Answer with the new \
+summary alone, in a few short paragraphs at most.";
+
+fn default_budget() -> usize {
+    3000
+}
+
+fn default_fetch() -> usize {
+    200
+}
+
+/// The `memory` table of a logic section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Window {
+    /// Tokens the summary and the turns may take, see `tokens`.
+    #[serde(default = "default_budget")]
+    pub budget: usize,
+    /// Messages looked at, at most.
+    #[serde(default = "default_fetch")]
+    pub fetch: usize,
+    /// Folds the messages falling out of the window into the summary,
+    /// otherwise they are just left out.
+    #[serde(default)]
+    pub summarize: bool,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            budget: default_budget(),
+            fetch: default_fetch(),
+            summarize: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memo {
+    pub role: String,
+    /// Prefixed with the name of the account that wrote it, see `signed`.
+    pub content: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub text: String,
+    /// `created` of the last message it covers.
+    pub upto: NaiveDateTime,
+}
+
+/// Messages and summaries by channel, no channel sees another's.
+#[allow(async_fn_in_trait)]
+pub trait Memory {
+    /// The newest `limit` messages of `channel` after `since`, oldest first.
+    async fn recent(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>>;
+
+    /// The oldest `limit` messages of `channel` after `since`, oldest first.
+    async fn after(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>>;
+
+    /// Keeps an answer of the model. What accounts write is kept as it is
+    /// sent.
+    async fn answer(&self, channel: i32, content: &str) -> Result<()>;
+
+    async fn summary(&self, channel: i32) -> Result<Option<Summary>>;
+
+    async fn save_summary(&self, channel: i32, summary: &Summary) -> Result<()>;
+}
+
+fn text(v: Value) -> String {
+    match v {
+        Value::String(x) => x,
+        x => x.to_string(),
+    }
+}
+
+/// `content` as said by `sender`, a channel has many members and the model
+/// is to tell them apart.
+pub fn signed(sender: Option<&str>, content: String) -> String {
+    match sender {
+        Some(name) => format!("{name}: {content}"),
+        None => content,
+    }
+}
+
+fn memo(x: TurnRecord) -> Memo {
+    Memo {
+        content: signed(x.sender.as_deref(), text(x.content)),
+        role: x.role,
+        created: x.created,
+    }
+}
+
+impl Memory for Model {
+    async fn recent(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>> {
+        let mut rows = self.list_turn(channel, since, limit as i64).await?;
+        rows.reverse();
+        Ok(rows.into_iter().map(memo).collect())
+    }
+
+    async fn after(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>> {
+        let rows = self.page_turn(channel, since, limit as i64).await?;
+        Ok(rows.into_iter().map(memo).collect())
+    }
+
+    async fn answer(&self, channel: i32, content: &str) -> Result<()> {
+        Ok(self.save_answer(channel, &content.into()).await?)
+    }
+
+    async fn summary(&self, channel: i32) -> Result<Option<Summary>> {
+        Ok(self
+            .channel_summary(channel)
+            .await?
+            .map(|(text, upto)| Summary { text, upto }))
+    }
+
+    async fn save_summary(&self, channel: i32, summary: &Summary) -> Result<()> {
+        Ok(Model::save_summary(self, channel, &summary.text, summary.upto).await?)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    memos: Vec<Memo>,
+    summary: Option<Summary>,
+}
+
+/// A `Memory` for tests, gone with the process.
+#[derive(Debug, Default)]
+pub struct InMemory {
+    channels: Mutex<HashMap<i32, Log>>,
+}
+
+impl InMemory {
+    /// Keeps a message of `role`, after every other of the channel.
+    pub fn push(&self, channel: i32, role: &str, content: &str) {
+        let mut channels = self.channels.lock().unwrap();
+        let log = channels.entry(channel).or_default();
+        let mut created = Utc::now().naive_utc();
+        if let Some(last) = log.memos.last()
+            && created <= last.created
+        {
+            created = last.created + TimeDelta::microseconds(1);
+        }
+        log.memos.push(Memo {
+            role: role.to_owned(),
+            content: content.to_owned(),
+            created,
+        });
+    }
+}
+
+impl Memory for InMemory {
+    async fn recent(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>> {
+        let channels = self.channels.lock().unwrap();
+        let Some(log) = channels.get(&channel) else {
+            return Ok(Vec::new());
+        };
+        let after: Vec<&Memo> = log
+            .memos
+            .iter()
+            .filter(|x| since.is_none_or(|t| x.created > t))
+            .collect();
+        let skip = after.len().saturating_sub(limit);
+        Ok(after.into_iter().skip(skip).cloned().collect())
+    }
+
+    async fn after(
+        &self,
+        channel: i32,
+        since: Option<NaiveDateTime>,
+        limit: usize,
+    ) -> Result<Vec<Memo>> {
+        let channels = self.channels.lock().unwrap();
+        let Some(log) = channels.get(&channel) else {
+            return Ok(Vec::new());
+        };
+        Ok(log
+            .memos
+            .iter()
+            .filter(|x| since.is_none_or(|t| x.created > t))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn answer(&self, channel: i32, content: &str) -> Result<()> {
+        self.push(channel, "assistant", content);
+        Ok(())
+    }
+
+    async fn summary(&self, channel: i32) -> Result<Option<Summary>> {
+        let channels = self.channels.lock().unwrap();
+        Ok(channels.get(&channel).and_then(|x| x.summary.clone()))
+    }
+
+    async fn save_summary(&self, channel: i32, summary: &Summary) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.entry(channel).or_default().summary = Some(summary.clone());
+        Ok(())
+    }
+}
+
+/// Folds messages into a summary.
+#[allow(async_fn_in_trait)]
+pub trait Summarizer {
+    /// `prior` extended with `turns`.
+    async fn summarize(&self, prior: Option<&str>, turns: &[Turn]) -> Result<String>;
+}
+
+impl Summarizer for Llm {
+    async fn summarize(&self, prior: Option<&str>, turns: &[Turn]) -> Result<String> {
+        let llm = Llm {
+            system: Some(SUMMARIZE.to_owned()),
+            json: false,
+            ..self.clone()
+        };
+        let mut ask = format!(
+            "Summary so far:\n{}\n\nMessages:\n",
+            prior.unwrap_or("none")
+        );
+        for x in turns {
+            ask.push_str(&format!("{}: {}\n", x.role, x.content));
+        }
+        llm.complete(&[Turn::new("user", ask)]).await
+    }
+}
+
+/// Rough tokens of a message: four characters each, and the framing.
+pub fn tokens(s: &str) -> usize {
+    s.chars().count().div_ceil(4) + 4
+}
+
+/// Extends `summary` with the messages before `until`, `fetch` at a time from
+/// the oldest one it does not cover.
+async fn fold<M: Memory, S: Summarizer>(
+    memory: &M,
+    channel: i32,
+    window: &Window,
+    summarizer: &S,
+    mut summary: Option<Summary>,
+    until: NaiveDateTime,
+) -> Result<Option<Summary>> {
+    let limit = window.fetch.max(1);
+    loop {
+        let since = summary.as_ref().map(|x| x.upto);
+        let mut page = memory.after(channel, since, limit).await?;
+        page.retain(|x| x.created < until);
+        let Some(last) = page.last() else {
+            return Ok(summary);
+        };
+        let turns: Vec<Turn> = page
+            .iter()
+            .map(|x| Turn::new(&x.role, x.content.clone()))
+            .collect();
+        let prior = summary.as_ref().map(|x| x.text.as_str());
+        match summarizer.summarize(prior, &turns).await {
+            Ok(text) => {
+                let s = Summary {
+                    text,
+                    upto: last.created,
+                };
+                memory.save_summary(channel, &s).await?;
+                summary = Some(s);
+            }
+            // The messages stay out of the summary until the next try
+            Err(e) => {
+                warn!("summarize channel {}: {:#}", channel, e);
+                return Ok(summary);
+            }
+        }
+        // Short of a page, `until` is reached
+        if page.len() < limit {
+            return Ok(summary);
+        }
+    }
+}
+
+/// The turns to send a model for `channel`: the summary as a system turn,
+/// then the newest messages that fit the budget, the newest one always.
+/// With a `summarizer`, the messages left out go into the summary.
+pub async fn context<M: Memory, S: Summarizer>(
+    memory: &M,
+    channel: i32,
+    window: &Window,
+    summarizer: Option<&S>,
+) -> Result<Vec<Turn>> {
+    let mut summary = memory.summary(channel).await?;
+    let memos = memory
+        .recent(channel, summary.as_ref().map(|x| x.upto), window.fetch)
+        .await?;
+
+    let mut left = window
+        .budget
+        .saturating_sub(summary.as_ref().map_or(0, |x| tokens(&x.text)));
+    let mut start = memos.len();
+    for (i, x) in memos.iter().enumerate().rev() {
+        let n = tokens(&x.content);
+        if n > left && start < memos.len() {
+            break;
+        }
+        left = left.saturating_sub(n);
+        start = i;
+    }
+
+    let (earlier, kept) = memos.split_at(start);
+    // Past `fetch` messages, some older than those looked at may be left
+    if let (Some(summarizer), Some(first)) = (summarizer, kept.first())
+        && (!earlier.is_empty() || memos.len() >= window.fetch)
+    {
+        summary = fold(memory, channel, window, summarizer, summary, first.created).await?;
+    }
+
+    let summary = summary.map(|x| {
+        Turn::new(
+            "system",
+            format!("Earlier in this conversation: {}", x.text),
+        )
+    });
+    Ok(summary
+        .into_iter()
+        .chain(kept.iter().map(|x| Turn::new(&x.role, x.content.clone())))
+        .collect())
+}
+
+#[cfg(test)]
+#[path = "memory_test.rs"]
+mod tests;
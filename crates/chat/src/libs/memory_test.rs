@@ -0,0 +1,168 @@
+use super::*;
+
+/// Joins what it is given, failing on demand.
+#[derive(Default)]
+struct Fold {
+    calls: Mutex<Vec<(Option<String>, usize)>>,
+    fail: bool,
+}
+
+impl Summarizer for Fold {
+    async fn summarize(&self, prior: Option<&str>, turns: &[Turn]) -> Result<String> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((prior.map(str::to_owned), turns.len()));
+        if self.fail {
+            anyhow::bail!("down");
+        }
+        let said: Vec<&str> = turns.iter().map(|x| x.content.as_str()).collect();
+        Ok(prior.into_iter().chain(said).collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Messages of 12 characters, 7 tokens each.
+fn memory(channel: i32, n: usize) -> InMemory {
+    let m = InMemory::default();
+    for i in 0..n {
+        m.push(channel, "user", &format!("message {i:04}"));
+    }
+    m
+}
+
+fn window(budget: usize) -> Window {
+    Window {
+        budget,
+        ..Default::default()
+    }
+}
+
+fn contents(turns: &[Turn]) -> Vec<&str> {
+    turns.iter().map(|x| x.content.as_str()).collect()
+}
+
+#[tokio::test]
+async fn newest_within_budget() {
+    let m = memory(1, 5);
+    let turns = context(&m, 1, &window(21), None::<&Fold>).await.unwrap();
+    assert_eq!(
+        contents(&turns),
+        ["message 0002", "message 0003", "message 0004"]
+    );
+    // The newest one even over budget
+    let turns = context(&m, 1, &window(1), None::<&Fold>).await.unwrap();
+    assert_eq!(contents(&turns), ["message 0004"]);
+}
+
+#[tokio::test]
+async fn summary_of_the_rest() {
+    let m = memory(1, 5);
+    let fold = Fold::default();
+    let turns = context(&m, 1, &window(100), Some(&fold)).await.unwrap();
+    assert_eq!(turns.len(), 5, "all fit, nothing to fold");
+    assert!(fold.calls.lock().unwrap().is_empty());
+
+    let turns = context(&m, 1, &window(21), Some(&fold)).await.unwrap();
+    assert_eq!(turns[0].role, "system");
+    assert_eq!(
+        turns[0].content,
+        "Earlier in this conversation: message 0000 message 0001"
+    );
+    assert_eq!(
+        contents(&turns[1..]),
+        ["message 0002", "message 0003", "message 0004"]
+    );
+
+    // Only what came after the summary is looked at again
+    m.answer(1, "reply").await.unwrap();
+    let turns = context(&m, 1, &window(100), Some(&fold)).await.unwrap();
+    assert_eq!(
+        contents(&turns[1..]),
+        ["message 0002", "message 0003", "message 0004", "reply"]
+    );
+    assert_eq!(turns[4].role, "assistant");
+    let calls = fold.calls.lock().unwrap();
+    assert_eq!(*calls, [(None, 2)]);
+}
+
+#[tokio::test]
+async fn failed_summary_keeps_the_old() {
+    let m = memory(1, 5);
+    let fold = Fold {
+        fail: true,
+        ..Default::default()
+    };
+    let turns = context(&m, 1, &window(21), Some(&fold)).await.unwrap();
+    assert_eq!(contents(&turns).len(), 3);
+    assert_eq!(m.summary(1).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn channels_apart() {
+    let m = memory(1, 3);
+    m.push(2, "user", "elsewhere");
+    let fold = Fold::default();
+    context(&m, 2, &window(1), Some(&fold)).await.unwrap();
+    assert_eq!(m.summary(1).await.unwrap(), None);
+
+    let turns = context(&m, 2, &window(100), None::<&Fold>).await.unwrap();
+    assert_eq!(contents(&turns), ["elsewhere"]);
+    let turns = context(&m, 1, &window(100), None::<&Fold>).await.unwrap();
+    assert_eq!(turns.len(), 3);
+    assert!(
+        context(&m, 3, &window(100), None::<&Fold>)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn summary_pages_past_fetch() {
+    let m = memory(1, 10);
+    let fold = Fold::default();
+    let w = Window {
+        budget: 14,
+        fetch: 3,
+        summarize: true,
+    };
+    let turns = context(&m, 1, &w, Some(&fold)).await.unwrap();
+    assert_eq!(contents(&turns[1..]), ["message 0008", "message 0009"]);
+    // Oldest first, none skipped
+    assert_eq!(
+        *fold.calls.lock().unwrap(),
+        [
+            (None, 3),
+            (Some("message 0000 message 0001 message 0002".to_owned()), 3),
+            (
+                Some(
+                    "message 0000 message 0001 message 0002 message 0003 message 0004 message 0005"
+                        .to_owned()
+                ),
+                2
+            ),
+        ]
+    );
+    let summary = m.summary(1).await.unwrap().unwrap();
+    let all: Vec<String> = (0..8).map(|i| format!("message {i:04}")).collect();
+    assert_eq!(summary.text, all.join(" "));
+}
+
+#[test]
+fn memos_name_the_sender() {
+    let created = Utc::now().naive_utc();
+    let said = memo(TurnRecord {
+        role: "user".into(),
+        created,
+        content: "hello".into(),
+        sender: Some("ann".into()),
+    });
+    assert_eq!(said.content, "ann: hello");
+    let answered = memo(TurnRecord {
+        role: "assistant".into(),
+        created,
+        content: serde_json::json!({"text": "hi"}),
+        sender: None,
+    });
+    assert_eq!(answered.content, r#"{"text":"hi"}"#);
+}
@@ -1,9 +1,14 @@
 pub mod admin;
+pub mod auth;
+pub mod channel;
 pub mod config;
 pub mod db;
 pub mod error;
 pub mod handler;
+pub mod llm;
 pub mod logic;
+pub mod memory;
 pub mod postgres;
 pub mod shared;
+pub mod structured;
 pub mod utils;
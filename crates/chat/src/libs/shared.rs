@@ -1,3 +1,4 @@
+use super::config::Auth;
 use super::db::Model;
 use axum::extract::FromRef;
 
@@ -6,6 +7,7 @@ pub type Db = Model;
 #[derive(Debug, Clone)]
 pub struct Shared {
     pub db: Db,
+    pub auth: Auth,
 }
 
 impl FromRef<Shared> for Db {
@@ -14,8 +16,14 @@ impl FromRef<Shared> for Db {
     }
 }
 
+impl FromRef<Shared> for Auth {
+    fn from_ref(input: &Shared) -> Self {
+        input.auth.clone()
+    }
+}
+
 impl Shared {
-    pub fn new(db: Model) -> Self {
-        Self { db }
+    pub fn new(db: Model, auth: Auth) -> Self {
+        Self { db, auth }
     }
 }
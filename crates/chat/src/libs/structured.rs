@@ -0,0 +1,85 @@
+//! A `Brick` tree from a model: asked for with the schema of `brick`, parsed,
+//! checked, and asked again with the error when that fails.
+use super::llm::{Llm, Turn};
+use anyhow::{Result, bail};
+use brick::{Brick, BrickOps};
+use indoc::indoc;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+use tracing::warn;
+
+const PROMPT: &str = indoc! {"
+    You design user interfaces. Answer with a single JSON object, a tree of
+    components following the JSON Schema below, and nothing else: no prose,
+    no code fences. Give an `id` to the components that are updated later,
+    ids are unique in the tree.
+"};
+
+static SYSTEM: LazyLock<String> = LazyLock::new(|| format!("{PROMPT}\n{}", brick::schema()));
+
+/// The JSON object in `reply`, without the fences and prose models tend to
+/// wrap it in.
+fn extract(reply: &str) -> &str {
+    match (reply.find('{'), reply.rfind('}')) {
+        (Some(a), Some(b)) if a < b => &reply[a..=b],
+        _ => reply.trim(),
+    }
+}
+
+/// Joins merge on ids, a repeated one would update several components.
+fn check(brick: &Brick) -> Result<()> {
+    fn walk<'a>(b: &'a Brick, ids: &mut HashSet<&'a str>) -> Result<()> {
+        if let Some(id) = b.get_id().as_deref()
+            && !ids.insert(id)
+        {
+            bail!("duplicate id {:?}", id);
+        }
+        for x in b.borrow_sub().into_iter().flatten() {
+            walk(x, ids)?;
+        }
+        Ok(())
+    }
+    walk(brick, &mut HashSet::new())
+}
+
+pub fn parse(reply: &str) -> Result<Brick> {
+    let brick: Brick = serde_json::from_str(extract(reply))?;
+    check(&brick)?;
+    Ok(brick)
+}
+
+/// Asks `llm` for the interface `ask` describes, up to `retries` more times
+/// with the error when the answer is not a valid tree.
+pub async fn generate(llm: &Llm, ask: &str, retries: usize) -> Result<Brick> {
+    // `stream` sends the system prompt, the one of the section after the schema
+    let system = match &llm.system {
+        Some(x) => format!("{}\n{x}", SYSTEM.as_str()),
+        None => SYSTEM.clone(),
+    };
+    let llm = Llm {
+        system: Some(system),
+        ..llm.clone()
+    };
+    let mut messages = vec![Turn::new("user", ask)];
+    let mut attempt = 0;
+    loop {
+        let reply = llm.complete(&messages).await?;
+        match parse(&reply) {
+            Ok(brick) => return Ok(brick),
+            Err(e) if attempt < retries => {
+                warn!("brick attempt {}: {}", attempt + 1, e);
+                messages.push(Turn::new("assistant", reply));
+                messages.push(Turn::new(
+                    "user",
+                    format!("That is not a valid component tree: {e}. Answer with the corrected JSON object only."),
+                ));
+                attempt += 1;
+            }
+            Err(e) => return Err(e.context(format!("no valid brick in {} attempts", attempt + 1))),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "structured_test.rs"]
+mod tests;
@@ -0,0 +1,107 @@
+use super::*;
+use agent::Model;
+use axum::{Json, Router, routing::post};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+const VALID: &str = r#"{"type": "text", "id": "a", "bind": {"value": {"default": "hi"}}}"#;
+const TWICE: &str =
+    r#"{"type": "case", "sub": [{"type": "text", "id": "a"}, {"type": "text", "id": "a"}]}"#;
+
+type Requests = Arc<Mutex<Vec<Value>>>;
+
+/// Answers the completion requests with `replies` in turn, as one delta each,
+/// keeping the requests.
+async fn mock(replies: &[&str]) -> (Llm, Requests) {
+    let replies = Arc::new(Mutex::new(
+        replies
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<VecDeque<_>>(),
+    ));
+    let requests = Requests::default();
+    let seen = requests.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move |Json(body): Json<Value>| async move {
+            seen.lock().unwrap().push(body);
+            let reply = replies.lock().unwrap().pop_front().unwrap_or_default();
+            let delta = json!({"choices": [{"delta": {"content": reply}}]});
+            axum::http::Response::builder()
+                .header("content-type", "text/event-stream")
+                .body(format!("data: {delta}\n\ndata: [DONE]\n\n"))
+                .unwrap()
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let llm = Llm {
+        model: Model {
+            base_url: format!("http://{addr}/v1"),
+            model: "mock".into(),
+            api_key: None,
+            api_key_env: "MOCK_API_KEY_UNSET".into(),
+            temperature: None,
+        },
+        system: Some("be brief".into()),
+        json: false,
+    };
+    (llm, requests)
+}
+
+fn roles(request: &Value) -> Vec<&str> {
+    request["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["role"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn extract_the_object() {
+    let reply = format!("Here it is:\n```json\n{VALID}\n```\nEnjoy.");
+    assert_eq!(extract(&reply), VALID);
+    assert_eq!(extract("  no object  "), "no object");
+}
+
+#[test]
+fn check_unique_ids() {
+    assert!(parse(VALID).is_ok());
+    let e = parse(TWICE).unwrap_err();
+    assert!(e.to_string().contains("duplicate id \"a\""), "{e}");
+    assert!(parse("{\"type\": \"nothing\"}").is_err());
+}
+
+#[tokio::test]
+async fn ask_again_with_the_error() {
+    let (llm, requests) = mock(&[TWICE, VALID]).await;
+    let brick = generate(&llm, "a greeting", 2).await.unwrap();
+    assert_eq!(brick.get_id().as_deref(), Some("a"));
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    // A single system turn, the schema then the prompt of the section
+    assert_eq!(roles(&requests[0]), ["system", "user"]);
+    let system = requests[0]["messages"][0]["content"].as_str().unwrap();
+    assert!(system.starts_with("You design user interfaces."));
+    assert!(system.ends_with("be brief"));
+    assert_eq!(roles(&requests[1]), ["system", "user", "assistant", "user"]);
+    assert_eq!(requests[1]["messages"][2]["content"], TWICE);
+    let again = requests[1]["messages"][3]["content"].as_str().unwrap();
+    assert!(again.contains("duplicate id"), "{again}");
+}
+
+#[tokio::test]
+async fn give_up_after_retries() {
+    let (llm, requests) = mock(&["no", "still no"]).await;
+    let e = generate(&llm, "a greeting", 1).await.unwrap_err();
+    assert!(
+        e.to_string().contains("no valid brick in 2 attempts"),
+        "{e}"
+    );
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
@@ -2,14 +2,17 @@
 mod libs;
 use anyhow::{Result, bail};
 use axum::{Router, extract::Json, routing::get};
+use axum_server::Handle;
 use libs::admin::data_router;
-use libs::config::{Config, LogFormat, Logic};
+use libs::config::{Config, LogFormat};
 use libs::error::HttpResult;
 use libs::postgres::connx;
 use libs::shared::Shared;
+use listenfd::ListenFd;
 use message::queue::MessageQueue;
+use message::server::{bind, serve, shutdown_signal};
 use serde_json::Value;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{
     EnvFilter, fmt::layer, prelude::__tracing_subscriber_SubscriberExt, registry,
     util::SubscriberInitExt,
@@ -19,6 +22,7 @@ use libs::db::Model;
 use libs::handler::{ChatMessage, Envelope, handler};
 use libs::logic::*;
 use message::time::Created;
+use tokio::sync::watch;
 use url::Url;
 use urlencoding::encode;
 
@@ -31,35 +35,50 @@ async fn main() -> Result<()> {
     let cfg = Config::new()?;
 
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (otel, provider) = match &cfg.trace.otlp {
+        Some(otlp) => Some(message::trace::layer(otlp, "chat")?),
+        None => None,
+    }
+    .unzip();
     match &cfg.trace.format {
         LogFormat::compact => {
-            registry().with(layer().compact()).with(filter).init();
+            registry()
+                .with(otel)
+                .with(layer().compact())
+                .with(filter)
+                .init();
         }
         LogFormat::json => {
-            registry().with(layer().json()).with(filter).init();
+            registry()
+                .with(otel)
+                .with(layer().json())
+                .with(filter)
+                .init();
         }
     };
 
-    dbg!(&cfg);
-
     let base_url = Url::parse(&cfg.gateway.base_url)?;
     let hc = reqwest::Client::new();
     for (k, v) in &cfg.hooks {
-        let r = hc.post(base_url.join(&encode(k))?).json(v).send().await;
+        let mut r = hc.post(base_url.join(&encode(k))?).json(v);
+        if let Some(key) = &cfg.gateway.api_key {
+            r = r.bearer_auth(key);
+        }
+        let r = r.send().await;
         info!("init hook {} [{}]", k, &r?.status());
     }
 
     let client = connx(&cfg.database).await?;
-    let shared = Shared::new(Model(client));
+    let shared = Shared::new(Model(client), cfg.auth.clone());
 
     let queue = cfg.queue;
 
-    let (outgo_tx, income_rx) = if !queue.disable {
+    let (outgo_tx, income_rx, drained) = if !queue.disable {
         queue
             .split::<ChatMessage<Created>, Envelope<Created>>()
             .await
     } else {
-        (None, None)
+        (None, None, None)
     };
 
     let Some(income_rx) = income_rx else {
@@ -69,29 +88,46 @@ async fn main() -> Result<()> {
         bail!("outgo channel invalid");
     };
 
-    info!("run as: Logic::{:?}", cfg.logic);
-    match cfg.logic {
-        Logic::Chat => {
-            let _ = handler(outgo_tx, income_rx, shared.clone(), chat).await;
-        }
-        Logic::Crm => {
-            let _ = handler(outgo_tx, income_rx, shared.clone(), crm).await;
-        }
-        Logic::Echo => {
-            let _ = handler(outgo_tx, income_rx, shared.clone(), echo).await;
-        }
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let registry = libs::logic::registry(cfg.logic)?;
+    if registry.is_empty() {
+        warn!("no logic enabled, events are dropped");
     }
+    let worker = handler(outgo_tx, income_rx, shared.clone(), shutdown_rx, registry).await?;
 
     let app = Router::new()
         .nest("/v1", data_router())
         .route("/is_ready", get(is_ready))
         .with_state(shared);
 
-    let addr = "0.0.0.0:3003";
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    info!("Listening on {}", addr);
+    let server = cfg.server;
+    let handle = Handle::new();
+    let listener = bind(&mut ListenFd::from_env(), 0, &server.listen)?;
+    let mut serving = tokio::spawn(serve(listener, server.tls.clone(), app, handle.clone()));
 
-    axum::serve(listener, app).await?;
+    tokio::select! {
+        r = &mut serving => return r?,
+        _ = shutdown_signal() => {},
+    }
+
+    info!("Shutting down");
+    let _ = shutdown_tx.send(true);
+    handle.graceful_shutdown(Some(server.grace()));
+    let _ = serving.await;
+    if tokio::time::timeout(server.grace(), worker).await.is_err() {
+        warn!("handler did not finish in time");
+    }
+    if let Some(drained) = drained
+        && tokio::time::timeout(server.grace(), drained.wait())
+            .await
+            .is_err()
+    {
+        warn!("outgo queue not drained in time");
+    }
+    if let Some(provider) = provider {
+        // Flushing blocks on the exporter
+        let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+    }
 
     Ok(())
 }
@@ -46,6 +46,12 @@ pub struct Outflow {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
     pub data: Value,
+    /// W3C `traceparent` started by the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+    /// Overrides the channel selected with `channel::select`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -63,11 +69,25 @@ pub enum Content<T> {
     #[serde(rename = "join")]
     Join(Influx<T>),
 
+    /// JSON Patch (RFC 6902) in `data`, against the layout of the last `create`.
+    #[serde(rename = "patch")]
+    Patch(Influx<Value>),
+
+    /// What the client sent was rejected, by the gateway or a service.
+    #[serde(rename = "error")]
+    Error(Fault),
+
     #[serde(rename = "empty")]
     #[default]
     Empty,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct Fault {
+    pub code: String,
+    pub message: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct InfluxTmpl {
     pub name: String,
@@ -0,0 +1,143 @@
+//! Drives a gateway with the inbound messages of a recording and diffs what
+//! comes back against the recorded outbound messages.
+//!
+//! replay <recording.jsonl> [--url <ws url>] [--protocol <subprotocol>] [--ignore <json pointer>]... [--settle <ms>] [--realtime]
+use anyhow::{Context, Result, bail};
+use futures::{SinkExt, StreamExt};
+use message::codec::{CodecType, Protocol};
+use message::record::{Direction, VOLATILE, diff, load, messages};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Message, client::IntoClientRequest, http::HeaderValue},
+};
+
+struct Opts {
+    recording: String,
+    url: String,
+    protocol: Protocol,
+    ignore: Vec<String>,
+    settle: Duration,
+    realtime: bool,
+}
+
+fn opts() -> Result<Opts> {
+    let mut args = std::env::args().skip(1);
+    let mut o = Opts {
+        recording: String::new(),
+        url: "ws://127.0.0.1:3000/channel".to_owned(),
+        // v1 gets full layouts, as recorded, rather than patches
+        protocol: Protocol {
+            version: 1,
+            ..Protocol::new(CodecType::Json)
+        },
+        ignore: VOLATILE.map(str::to_owned).to_vec(),
+        settle: Duration::from_millis(2000),
+        realtime: false,
+    };
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--url" => o.url = args.next().context("--url needs a value")?,
+            "--protocol" => {
+                o.protocol = args.next().context("--protocol needs a value")?.parse()?
+            }
+            "--ignore" => o
+                .ignore
+                .push(args.next().context("--ignore needs a value")?),
+            "--settle" => {
+                o.settle =
+                    Duration::from_millis(args.next().context("--settle needs a value")?.parse()?)
+            }
+            "--realtime" => o.realtime = true,
+            x if x.starts_with("--") => bail!("unknown option {}", x),
+            x => o.recording = x.to_owned(),
+        }
+    }
+    if o.recording.is_empty() {
+        bail!(
+            "usage: replay <recording.jsonl> [--url <ws url>] [--protocol <subprotocol>] [--ignore <json pointer>]... [--settle <ms>] [--realtime]"
+        );
+    }
+    Ok(o)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let o = opts()?;
+    let entries = load(&o.recording)?;
+    let expected = messages(&entries, Direction::Outbound);
+
+    let mut req = o.url.as_str().into_client_request()?;
+    req.headers_mut().insert(
+        "sec-websocket-protocol",
+        HeaderValue::from_str(&o.protocol.to_string())?,
+    );
+    let (ws, resp) = connect_async(req).await.with_context(|| o.url.clone())?;
+    // Frames both ways follow what the gateway picked
+    let protocol = match resp.headers().get("sec-websocket-protocol") {
+        Some(x) => x.to_str()?.parse()?,
+        None => o.protocol,
+    };
+    let (mut write, mut read) = ws.split();
+
+    let (tx, mut rx) = unbounded_channel::<Value>();
+    tokio::spawn(async move {
+        while let Some(Ok(m)) = read.next().await {
+            let v = match m {
+                Message::Text(t) => protocol.decode(t.as_bytes()).ok(),
+                Message::Binary(b) => protocol.decode(&b).ok(),
+                _ => continue,
+            };
+            if let Some(v) = v
+                && tx.send(v).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut last = None;
+    for e in entries.iter().filter(|e| e.direction == Direction::Inbound) {
+        if o.realtime
+            && let Some(last) = last
+            && let Ok(d) = e.time.signed_duration_since(last).to_std()
+        {
+            sleep(d).await;
+        }
+        last = Some(e.time);
+        // The recorded `ChatMessage` wraps what the client sent in `content`
+        let payload = e.message.get("content").unwrap_or(&e.message);
+        let bytes = protocol.encode(payload)?;
+        let frame = if protocol.is_binary() {
+            Message::Binary(bytes.into())
+        } else {
+            Message::Text(String::from_utf8(bytes)?.into())
+        };
+        write.send(frame).await?;
+    }
+
+    let mut actual = Vec::new();
+    while let Ok(Some(v)) = timeout(o.settle, rx.recv()).await {
+        actual.push(v);
+    }
+    let _ = write.close().await;
+
+    let mismatches = diff(&expected, &actual, &o.ignore);
+    println!(
+        "{}: {} expected, {} received, {} mismatches",
+        o.recording,
+        expected.len(),
+        actual.len(),
+        mismatches.len()
+    );
+    for m in &mismatches {
+        println!("  {}", m);
+    }
+    if !mismatches.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
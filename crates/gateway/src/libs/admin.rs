@@ -1,25 +1,33 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 
 use arc_swap::ArcSwap;
 use axum::{
-    Router,
-    extract::{Json, Path, Request, State},
-    http::{StatusCode, header::ACCEPT},
+    Extension, Router,
+    extract::{ConnectInfo, Json, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header::ACCEPT},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
 use indexmap::IndexMap;
 use message::{
     Envelope,
+    codec::CodecType,
     session::{Session, SessionCount, SessionInfo},
     time::Created,
 };
 use minijinja::Environment;
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value, from_str};
+use time::OffsetDateTime;
 
-use super::config::{ASSETS_PATH, Config, Hooks};
+use super::auth::Principal;
+use super::config::{ASSETS_PATH, Config, Hooks, LiveConfig, ReloadStatus};
 use super::error::HttpResult;
 use super::shared::{Arw, Asession, Sender, StateChat};
+use super::store::HookChange;
+use super::tap::tap;
 
 async fn send(
     State(session): State<Asession<Sender>>,
@@ -64,6 +72,94 @@ async fn info(
     Json(u.unwrap_or_else(Map::new))
 }
 
+#[derive(Debug, Serialize)]
+struct Connection {
+    id: Session,
+    #[serde(with = "time::serde::rfc3339")]
+    created: OffsetDateTime,
+    codec: CodecType,
+    protocol: String,
+    remote: SocketAddr,
+    bytes_in: u64,
+    bytes_out: u64,
+    info: Map<String, Value>,
+}
+
+async fn detail(
+    Path(user): Path<String>,
+    State(session): State<Asession<Sender>>,
+) -> HttpResult<Response> {
+    let id: Session = user.as_str().into();
+    let Some(c) = session.get(&id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    Ok(Json(Connection {
+        created: c.created,
+        codec: c.codec,
+        protocol: c.protocol.to_string(),
+        remote: c.remote,
+        bytes_in: c.stats.bytes_in.load(Ordering::Relaxed),
+        bytes_out: c.stats.bytes_out.load(Ordering::Relaxed),
+        info: c.info.clone(),
+        id,
+    })
+    .into_response())
+}
+
+/// Disconnects the session, its logout hook runs as on a normal close.
+async fn kick(
+    Path(user): Path<String>,
+    State(session): State<Asession<Sender>>,
+) -> HttpResult<(StatusCode, Json<bool>)> {
+    let Some((_, c)) = session.remove(&user.as_str().into()) else {
+        return Ok((StatusCode::NOT_FOUND, Json(false)));
+    };
+    let _ = c.term.send(true).await;
+    Ok((StatusCode::OK, Json(true)))
+}
+
+async fn update_info(
+    Path(user): Path<String>,
+    State(state): State<StateChat<Sender>>,
+    Json(payload): Json<Map<String, Value>>,
+) -> HttpResult<(StatusCode, Json<bool>)> {
+    let id: Session = user.as_str().into();
+    let Some(mut c) = state.session.get_mut(&id) else {
+        return Ok((StatusCode::NOT_FOUND, Json(false)));
+    };
+    c.info = payload.clone();
+    // Keep a JWT reconnect from restoring the old info
+    if let Some(mut k) = state.known.get_mut(&id) {
+        *k = payload;
+    }
+    Ok((StatusCode::OK, Json(true)))
+}
+
+#[derive(Debug, Deserialize)]
+struct TailOpts {
+    #[serde(default = "default_tail_limit")]
+    limit: usize,
+}
+
+fn default_tail_limit() -> usize {
+    20
+}
+
+async fn tail(
+    Path(user): Path<String>,
+    Query(opts): Query<TailOpts>,
+    State(session): State<Asession<Sender>>,
+) -> HttpResult<Response> {
+    let Some(c) = session.get(&user.as_str().into()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    Ok(Json(c.stats.recent(opts.limit)).into_response())
+}
+
+async fn generation(State(state): State<StateChat<Sender>>) -> Json<ReloadStatus> {
+    Json((**state.live.status.load()).clone())
+}
+
 struct Req<'a>(&'a Request);
 impl std::fmt::Display for Req<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -78,8 +174,12 @@ impl std::fmt::Display for Req<'_> {
 pub fn admin_router() -> Router<StateChat<Sender>> {
     Router::new()
         .route("/sessions", get(list))
-        .route("/info/{user}", get(info))
+        .route("/sessions/{user}", get(detail).delete(kick))
+        .route("/sessions/{user}/messages", get(tail))
+        .route("/tap", get(tap))
+        .route("/info/{user}", get(info).put(update_info))
         .route("/send", post(send))
+        .route("/config", get(generation))
 }
 
 async fn render(Path(name): Path<String>, Json(payload): Json<Value>) -> HttpResult<Response> {
@@ -167,21 +267,70 @@ async fn list_hook(
     Ok((StatusCode::OK, Json(s.hooks.clone())))
 }
 
+/// Who made a config change: the API key name, else the `x-actor` header, plus the peer address.
+fn actor(principal: Option<Extension<Principal>>, headers: &HeaderMap, addr: SocketAddr) -> String {
+    principal
+        .map(|Extension(Principal(x))| x)
+        .or_else(|| {
+            headers
+                .get("x-actor")
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_owned())
+        })
+        .map(|x| format!("{} ({})", x, addr))
+        .unwrap_or_else(|| addr.to_string())
+}
+
 async fn update_hook(
     Path(hook): Path<String>,
-    State(config): State<Arc<ArcSwap<Config>>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    State(live): State<LiveConfig>,
     Json(payload): Json<Hooks>,
 ) -> HttpResult<(StatusCode, Json<bool>)> {
-    config.rcu(|old| {
-        let mut s = (**old).clone();
-        s.hooks.insert(hook.clone(), payload.clone());
-        Arc::new(s)
-    });
+    let actor = actor(principal, &headers, addr);
+    live.update_hook(&hook, Some(payload), actor).await?;
     Ok((StatusCode::OK, Json(true)))
 }
 
+async fn delete_hook(
+    Path(hook): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    State(live): State<LiveConfig>,
+) -> HttpResult<(StatusCode, Json<bool>)> {
+    if !live.data.load().hooks.contains_key(&hook) {
+        return Ok((StatusCode::NOT_FOUND, Json(false)));
+    }
+    let actor = actor(principal, &headers, addr);
+    live.update_hook(&hook, None, actor).await?;
+    Ok((StatusCode::OK, Json(true)))
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryOpts {
+    hook: Option<String>,
+    #[serde(default = "default_history_limit")]
+    limit: usize,
+}
+
+fn default_history_limit() -> usize {
+    100
+}
+
+async fn history(
+    Query(opts): Query<HistoryOpts>,
+    State(live): State<LiveConfig>,
+) -> HttpResult<Json<Vec<HookChange>>> {
+    let r = live.store.history(opts.hook.as_deref(), opts.limit).await?;
+    Ok(Json(r))
+}
+
 pub fn config_router() -> Router<StateChat<Sender>> {
     Router::new()
         .route("/hooks", get(list_hook))
-        .route("/hooks/{hook}", post(update_hook))
+        .route("/hooks/{hook}", post(update_hook).delete(delete_hook))
+        .route("/history", get(history))
 }
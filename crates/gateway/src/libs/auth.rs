@@ -0,0 +1,134 @@
+use super::config::{Auth, Hook, Scope};
+use super::shared::{Sender, StateChat};
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::CookieJar;
+use message::session::SessionInfo;
+use serde_json::{Map, Value};
+use std::time::Instant;
+use tracing::{Instrument, info, info_span};
+
+/// Name of the API key a request was authenticated with, used as the audit actor.
+#[derive(Debug, Clone)]
+pub struct Principal(pub String);
+
+fn bearer(req: &Request) -> Option<&str> {
+    if let Some(x) = req.headers().get("x-api-key") {
+        return x.to_str().ok();
+    }
+    req.headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Compares without short-circuiting so the timing does not leak the key prefix.
+fn eq_ct(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn authorize(
+    scope: Scope,
+    state: StateChat<Sender>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let config = state.config.load_full();
+    if config.admin.keys.is_empty() {
+        return next.run(req).await;
+    }
+    let Some(token) = bearer(&req) else {
+        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
+    };
+    let Some(key) = config
+        .admin
+        .keys
+        .iter()
+        .find(|k| eq_ct(k.key.as_bytes(), token.as_bytes()))
+    else {
+        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
+    };
+    if !key.scopes.contains(&scope) {
+        return (StatusCode::FORBIDDEN, "FORBIDDEN").into_response();
+    }
+    req.extensions_mut().insert(Principal(key.name.clone()));
+    next.run(req).await
+}
+
+pub async fn require_admin(
+    State(state): State<StateChat<Sender>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    authorize(Scope::Admin, state, req, next).await
+}
+
+pub async fn require_config(
+    State(state): State<StateChat<Sender>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    authorize(Scope::Config, state, req, next).await
+}
+
+/// Establishes the session of a `/channel` or `/events` client through the
+/// configured auth mode, `None` when it is rejected.
+pub async fn login(
+    state: &StateChat<Sender>,
+    q: &mut Map<String, Value>,
+    jar: &CookieJar,
+) -> Option<SessionInfo> {
+    let s = state.config.load_full();
+    let tmpls = state.tmpls.load_full();
+    let login = &s.hooks.get("login")?[0];
+
+    if s.login_with_cookie {
+        let cookie: Value = jar.iter().map(|c| (c.name(), c.value())).collect();
+        q.insert("Cookie".to_owned(), cookie);
+    }
+
+    let start = Instant::now();
+    let a = match &s.auth {
+        Auth::webhook => login
+            .handle(q, tmpls)
+            .instrument(info_span!("hook", hook = "login"))
+            .await
+            .map_err(anyhow::Error::from),
+        Auth::jwt(jwt) => jwt
+            .login(q, jar, login, &state.known, tmpls)
+            .instrument(info_span!("hook", hook = "login"))
+            .await
+            .inspect_err(|e| info!("jwt login rejected: {:#}", e)),
+    };
+    state.metrics.hook("login", start, &a);
+    a.ok()
+}
+
+/// The logout hook as configured when the session was established.
+pub fn logout_hook(state: &StateChat<Sender>) -> Option<Hook> {
+    state
+        .config
+        .load()
+        .hooks
+        .get("logout")
+        .map(|x| x[0].clone())
+}
+
+/// Runs the logout hook. What the login hook said about a JWT subject is
+/// forgotten once none of its connections is left.
+pub async fn logout(state: &StateChat<Sender>, hook: &Hook, session: SessionInfo) {
+    state
+        .known
+        .remove_if(&session.id, |id, _| !state.session.contains_key(id));
+    let start = Instant::now();
+    let r = hook
+        .handle::<Value>(&session.into(), state.tmpls.load_full())
+        .instrument(info_span!("hook", hook = "logout"))
+        .await;
+    state.metrics.hook("logout", start, &r);
+}
@@ -1,3 +1,11 @@
+use super::jwt::Jwt;
+use super::record::Record;
+use super::store::{
+    ChangeAction, ConfigStore, HookChange, HookOverlay, StoreConfig, apply_overlay,
+};
+use super::template::Tmpls;
+use anyhow::bail;
+use arc_swap::ArcSwap;
 use figment::{
     Figment, Result,
     providers::{Env, Format, Toml},
@@ -5,13 +13,18 @@ use figment::{
 use indexmap::IndexMap;
 use message::codec::CodecType;
 use message::config::Queue;
+use message::server::Server;
+use message::trace::Otlp;
 use notify::{Event, RecursiveMode, Result as ResultN, Watcher, recommended_watcher};
 use serde::{Deserialize, Serialize};
 use serde_with::{OneOrMany, serde_as};
 use std::ops::Deref;
 use std::path::Path;
-use std::sync::{Arc, mpsc::channel};
-use tokio::sync::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::{Mutex, mpsc::unbounded_channel};
+use tracing::{error, info};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
@@ -63,6 +76,10 @@ fn default_accept() -> String {
 pub type HookMap = IndexMap<String, Hooks>;
 
 pub const ASSETS_PATH: &str = "manifest";
+pub const CONFIG_FILE: &str = "gateway.toml";
+
+/// Editors usually emit a burst of events per save, wait for it to settle.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub enum LogFormat {
@@ -76,6 +93,64 @@ pub enum LogFormat {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Log {
     pub format: LogFormat,
+    pub otlp: Option<Otlp>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// `/admin`: inspect sessions and push messages to them.
+    #[serde(rename = "admin")]
+    Admin,
+    /// `/config`: change hooks and read their history.
+    #[serde(rename = "config")]
+    Config,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Admin {
+    /// Serve `/admin` and `/config` on a separate address (e.g. `127.0.0.1:3001`)
+    /// instead of the public listener. Read at startup only.
+    pub listen: Option<String>,
+    /// Authentication is disabled when no key is configured.
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+    /// Messages kept per session for `/admin/sessions/{id}/messages`.
+    #[serde(default = "default_tail")]
+    pub tail: usize,
+}
+
+fn default_tail() -> usize {
+    20
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            keys: Vec::new(),
+            tail: default_tail(),
+        }
+    }
+}
+
+/// How `/channel` establishes the session.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "mode")]
+pub enum Auth {
+    /// Call the `login` hook on every upgrade.
+    #[default]
+    #[allow(non_camel_case_types)]
+    webhook,
+    /// Verify a token locally, the `login` hook is only called on first sight.
+    #[allow(non_camel_case_types)]
+    jwt(Jwt),
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -87,46 +162,213 @@ pub struct Config {
     pub login_with_cookie: bool,
     #[serde(default)]
     pub codec: CodecType,
+    /// Persistence for hooks changed at runtime, kept in memory when absent.
+    pub store: Option<StoreConfig>,
+    #[serde(default)]
+    pub admin: Admin,
+    /// Read at startup only.
+    #[serde(default)]
+    pub server: Server,
+    /// Read at startup only.
+    pub record: Option<Record>,
+    #[serde(default)]
+    pub auth: Auth,
 }
 
 impl Config {
     pub fn new() -> Result<Self> {
         Figment::new()
-            .merge(Toml::file("gateway.toml"))
+            .merge(Toml::file(CONFIG_FILE))
             .merge(Env::prefixed("GATEWAY_").split("_"))
             .extract()
     }
+
+    /// Checks what the websocket handler relies on at runtime, so a broken
+    /// edit is rejected before it is swapped in.
+    pub fn validate(&self, tmpls: &Tmpls<'_>) -> anyhow::Result<()> {
+        for name in ["login", "logout"] {
+            match self.hooks.get(name) {
+                Some(h) if !h.is_empty() => {}
+                _ => bail!("missing `{}` hook", name),
+            }
+        }
+        for (name, hooks) in &self.hooks {
+            for h in hooks {
+                let tmpl = match &h.variant {
+                    HookVariant::Path { path } => Some(path),
+                    HookVariant::Webhook { render, .. } => {
+                        render.as_ref().filter(|x| !x.is_empty())
+                    }
+                };
+                if let Some(t) = tmpl
+                    && tmpls.get_template(t).is_err()
+                {
+                    bail!("hook `{}` refers to unknown template `{}`", name, t);
+                }
+            }
+        }
+        if tmpls.get_template("webhook_error.json").is_err() {
+            bail!("missing template `webhook_error.json`");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ReloadStatus {
+    pub generation: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub loaded: OffsetDateTime,
+    /// Error of the last failed reload, cleared by the next successful one.
+    pub error: Option<String>,
 }
 
+#[derive(Debug, Clone)]
 pub struct LiveConfig {
-    pub data: Arc<Mutex<Config>>,
+    pub data: Arc<ArcSwap<Config>>,
+    pub tmpls: Arc<ArcSwap<Tmpls<'static>>>,
+    pub status: Arc<ArcSwap<ReloadStatus>>,
+    /// Hooks changed through the API, re-applied on top of every reload.
+    pub overlay: Arc<ArcSwap<HookOverlay>>,
+    pub store: Arc<ConfigStore>,
+    writer: Arc<Mutex<()>>,
 }
 
 impl LiveConfig {
-    pub fn new() -> Result<Self> {
-        let x = Config::new()?;
+    /// The `store` section is only read here, changing it requires a restart.
+    pub async fn new() -> anyhow::Result<Self> {
+        let store = ConfigStore::new(Config::new()?.store.as_ref()).await?;
+        let overlay = store.overlay().await?;
+        let (config, tmpls) = Self::load(&overlay)?;
         Ok(Self {
-            data: Arc::new(Mutex::new(x)),
+            data: Arc::new(ArcSwap::from_pointee(config)),
+            tmpls: Arc::new(ArcSwap::from_pointee(tmpls)),
+            status: Arc::new(ArcSwap::from_pointee(ReloadStatus {
+                generation: 0,
+                loaded: OffsetDateTime::now_utc(),
+                error: None,
+            })),
+            overlay: Arc::new(ArcSwap::from_pointee(overlay)),
+            store: Arc::new(store),
+            writer: Arc::new(Mutex::new(())),
         })
     }
 
-    #[allow(dead_code)]
-    pub async fn listen(&mut self) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let (tx, rx) = channel::<ResultN<Event>>();
-        let mut watcher = recommended_watcher(tx)?;
-        watcher.watch(Path::new("config.toml"), RecursiveMode::Recursive)?;
-        let d = self.data.clone();
-        tokio::task::spawn_blocking(|| async move {
-            while let Ok(res) = rx.recv() {
-                if res?.kind.is_modify() {
-                    let n = Config::new()?;
-                    dbg!("config update: {:?}", &n);
-                    let mut x = d.lock().await;
-                    *x = n;
+    fn load(overlay: &HookOverlay) -> anyhow::Result<(Config, Tmpls<'static>)> {
+        let mut config = Config::new()?;
+        apply_overlay(&mut config.hooks, overlay);
+        if let Auth::jwt(jwt) = &mut config.auth {
+            jwt.load_keys()?;
+        }
+        let tmpls = Tmpls::new(ASSETS_PATH)?;
+        config.validate(&tmpls)?;
+        Ok((config, tmpls))
+    }
+
+    /// Validates, persists and applies a runtime hook change, `None` deletes the hook.
+    pub async fn update_hook(
+        &self,
+        hook: &str,
+        value: Option<Hooks>,
+        actor: String,
+    ) -> anyhow::Result<()> {
+        let _guard = self.writer.lock().await;
+        let mut config = (**self.data.load()).clone();
+        let mut overlay = (**self.overlay.load()).clone();
+        overlay.insert(hook.to_owned(), value.clone());
+        apply_overlay(&mut config.hooks, &overlay);
+        config.validate(&self.tmpls.load())?;
+
+        let action = match value {
+            Some(_) => ChangeAction::Update,
+            None => ChangeAction::Delete,
+        };
+        self.store
+            .record(HookChange {
+                created: OffsetDateTime::now_utc(),
+                actor,
+                action,
+                hook: hook.to_owned(),
+                value,
+            })
+            .await?;
+
+        self.overlay.store(Arc::new(overlay));
+        self.data.store(Arc::new(config));
+        Ok(())
+    }
+
+    /// Rebuilds `Config` and `Tmpls` from disk and swaps them in.
+    /// On failure the running config is kept and the error is recorded.
+    pub fn reload(&self) -> anyhow::Result<u64> {
+        match Self::load(&self.overlay.load()) {
+            Ok((config, tmpls)) => {
+                self.data.store(Arc::new(config));
+                self.tmpls.store(Arc::new(tmpls));
+                let generation = self.status.load().generation + 1;
+                self.status.store(Arc::new(ReloadStatus {
+                    generation,
+                    loaded: OffsetDateTime::now_utc(),
+                    error: None,
+                }));
+                Ok(generation)
+            }
+            Err(e) => {
+                let msg = format!("{:#}", e);
+                self.status.rcu(|s| ReloadStatus {
+                    error: Some(msg.clone()),
+                    ..(**s).clone()
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Watches `gateway.toml` and the `manifest/` directory, reloading on change.
+    pub fn listen(&self) -> ResultN<()> {
+        let (tx, mut rx) = unbounded_channel::<ResultN<Event>>();
+        let mut watcher = recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
+        // Watch the directory rather than the file, editors often replace it on save
+        watcher.watch(Path::new("."), RecursiveMode::NonRecursive)?;
+        watcher.watch(Path::new(ASSETS_PATH), RecursiveMode::Recursive)?;
+
+        let live = self.clone();
+        tokio::spawn(async move {
+            let _watcher = watcher;
+            while let Some(res) = rx.recv().await {
+                let mut changed = is_relevant(res);
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                while let Ok(res) = rx.try_recv() {
+                    changed |= is_relevant(res);
+                }
+                if !changed {
+                    continue;
+                }
+                match live.reload() {
+                    Ok(generation) => info!("config reloaded, generation {}", generation),
+                    Err(e) => error!("config reload failed: {:#}", e),
                 }
             }
-            Ok::<(), Box<dyn std::error::Error>>(())
         });
         Ok(())
     }
 }
+
+fn is_relevant(res: ResultN<Event>) -> bool {
+    let event = match res {
+        Ok(event) => event,
+        Err(e) => {
+            error!("watch error: {:?}", e);
+            return false;
+        }
+    };
+    if event.kind.is_access() {
+        return false;
+    }
+    event.paths.iter().any(|p| {
+        p.file_name().is_some_and(|n| n == CONFIG_FILE)
+            || p.components().any(|c| c.as_os_str() == ASSETS_PATH)
+    })
+}
@@ -0,0 +1,130 @@
+use super::shared::encode_ws;
+use axum::extract::ws::Message;
+use dashmap::DashMap;
+use message::{codec::Protocol, session::Session};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// First protocol version whose clients apply `patch` contents.
+pub const DELTA_VERSION: u16 = 2;
+
+/// Baselines not updated for that long are dropped when a connection opens.
+const BASELINE_TTL: Duration = Duration::from_secs(3600);
+
+/// Last layout sent to each session, kept across its connections so a client
+/// that reconnects with its store intact can be sent a `patch`.
+#[derive(Debug, Clone, Default)]
+pub struct Baselines(Arc<DashMap<Session, (Value, Instant)>>);
+
+impl Baselines {
+    pub fn get(&self, sid: &Session) -> Option<Value> {
+        self.0.get(sid).map(|x| x.0.clone())
+    }
+
+    pub fn set(&self, sid: &Session, layout: Value) {
+        self.0.insert(sid.clone(), (layout, Instant::now()));
+    }
+
+    pub fn forget(&self, sid: &Session) {
+        self.0.remove(sid);
+    }
+
+    pub fn prune(&self) {
+        self.0.retain(|_, (_, at)| at.elapsed() < BASELINE_TTL);
+    }
+}
+
+/// Turns each `create` sent to a session into a JSON Patch against the last
+/// layout the session got, when that is smaller. A client without the
+/// baseline must have it forgotten before connecting.
+#[derive(Debug)]
+pub struct Delta {
+    protocol: Protocol,
+    sid: Session,
+    baselines: Baselines,
+}
+
+impl Delta {
+    pub fn new(protocol: Protocol, sid: &Session, baselines: &Baselines) -> Self {
+        baselines.prune();
+        // Whatever the session got is lost on a client that cannot patch
+        if protocol.version < DELTA_VERSION {
+            baselines.forget(sid);
+        }
+        Self {
+            protocol,
+            sid: sid.clone(),
+            baselines: baselines.clone(),
+        }
+    }
+
+    /// Encodes `msg` for the socket, with its `create` turned into a `patch`
+    /// when that is smaller.
+    pub fn encode<T: Serialize>(&self, msg: &T) -> Option<Message> {
+        let mut next = None;
+        let encoded = match self.compress(msg, &mut next) {
+            Some(v) => encode_ws(self.protocol, &v),
+            None => encode_ws(self.protocol, msg),
+        }?;
+        // A message that fails to encode never reaches the client, nor does
+        // its layout become the baseline
+        if let Some(layout) = next {
+            self.baselines.set(&self.sid, layout);
+        }
+        Some(encoded)
+    }
+
+    /// `next` is left with the layout of the last `create`, if any.
+    fn compress<T: Serialize>(&self, msg: &T, next: &mut Option<Value>) -> Option<Value> {
+        if self.protocol.version < DELTA_VERSION {
+            return None;
+        }
+        let mut v = serde_json::to_value(msg).ok()?;
+        let changed = match v.get_mut("content")? {
+            Value::Array(items) => {
+                // Every item, each `create` is the baseline of the next one
+                let mut changed = false;
+                for x in items {
+                    changed |= self.patch(next, x);
+                }
+                changed
+            }
+            x => self.patch(next, x),
+        };
+        changed.then_some(v)
+    }
+
+    fn patch(&self, next: &mut Option<Value>, content: &mut Value) -> bool {
+        if content.get("action").and_then(|x| x.as_str()) != Some("create") {
+            return false;
+        }
+        let Some(data) = content.get("data") else {
+            return false;
+        };
+        let prior = next
+            .replace(data.clone())
+            .or_else(|| self.baselines.get(&self.sid));
+        let Some(base) = prior.as_ref() else {
+            return false;
+        };
+        let Ok(ops) = serde_json::to_value(json_patch::diff(base, data)) else {
+            return false;
+        };
+        // Sizes as JSON are a good enough proxy for the binary codecs
+        if ops.to_string().len() >= data.to_string().len() {
+            return false;
+        }
+        *content = json!({
+            "action": "patch",
+            "event": content.get("event").cloned().unwrap_or_default(),
+            "data": ops,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+#[path = "delta_test.rs"]
+mod tests;
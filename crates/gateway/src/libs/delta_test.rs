@@ -0,0 +1,83 @@
+use super::*;
+use message::codec::CodecType;
+
+fn layout(title: &str) -> Value {
+    json!({
+        "content": {
+            "action": "create",
+            "event": "chat",
+            "data": {"type": "page", "title": title, "items": ["a", "b", "c", "d", "e", "f"]}
+        }
+    })
+}
+
+fn sent(delta: &Delta, msg: &Value) -> Value {
+    match delta.encode(msg) {
+        Some(Message::Text(t)) => serde_json::from_str(&t).unwrap(),
+        x => panic!("{:?}", x),
+    }
+}
+
+fn action(v: &Value) -> &str {
+    v["content"]["action"].as_str().unwrap()
+}
+
+#[test]
+fn reconnect_gets_patch() {
+    let baselines = Baselines::default();
+    let sid = Session::from("s1");
+    let protocol = Protocol::new(CodecType::Json);
+
+    let first = Delta::new(protocol, &sid, &baselines);
+    assert_eq!(action(&sent(&first, &layout("one"))), "create");
+    drop(first);
+
+    // Same session on a new connection, its client kept the layout
+    let again = Delta::new(protocol, &sid, &baselines);
+    let v = sent(&again, &layout("two"));
+    assert_eq!(action(&v), "patch");
+    assert_eq!(
+        v["content"]["data"],
+        json!([{"op": "replace", "path": "/title", "value": "two"}])
+    );
+
+    // Other sessions have their own baseline
+    let other = Delta::new(protocol, &Session::from("s2"), &baselines);
+    assert_eq!(action(&sent(&other, &layout("two"))), "create");
+}
+
+#[test]
+fn forgotten_baseline() {
+    let baselines = Baselines::default();
+    let sid = Session::from("s1");
+    let protocol = Protocol::new(CodecType::Json);
+    sent(&Delta::new(protocol, &sid, &baselines), &layout("one"));
+
+    // A client with a fresh store
+    baselines.forget(&sid);
+    let v = sent(&Delta::new(protocol, &sid, &baselines), &layout("two"));
+    assert_eq!(action(&v), "create");
+    assert_eq!(v["content"]["data"]["title"], "two");
+}
+
+#[test]
+fn older_protocol_drops_baseline() {
+    let baselines = Baselines::default();
+    let sid = Session::from("s1");
+    let protocol = Protocol::new(CodecType::Json);
+    sent(&Delta::new(protocol, &sid, &baselines), &layout("one"));
+
+    let old = Protocol {
+        version: DELTA_VERSION - 1,
+        ..protocol
+    };
+    let delta = Delta::new(old, &sid, &baselines);
+    assert_eq!(baselines.get(&sid), None);
+    assert_eq!(action(&sent(&delta, &layout("two"))), "create");
+    assert_eq!(baselines.get(&sid), None);
+
+    // Back on a recent client, nothing to patch against
+    let v = sent(&Delta::new(protocol, &sid, &baselines), &layout("three"));
+    assert_eq!(action(&v), "create");
+    assert_eq!(baselines.get(&sid).unwrap()["title"], "three");
+}
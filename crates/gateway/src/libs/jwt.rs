@@ -0,0 +1,144 @@
+use super::config::Hook;
+use super::shared::Info;
+use super::template::Tmpls;
+use anyhow::{Context, Result, anyhow, bail};
+use axum_extra::extract::cookie::CookieJar;
+use dashmap::DashMap;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use message::session::{Session, SessionInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Jwt {
+    #[serde(default = "default_alg")]
+    pub alg: Algorithm,
+    /// Shared secret for the HMAC algorithms.
+    pub secret: Option<String>,
+    /// Local JWKS file for the asymmetric algorithms.
+    pub jwks: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// Query parameter carrying the token.
+    #[serde(default = "default_token")]
+    pub param: String,
+    /// Cookie carrying the token, checked when the query parameter is absent.
+    #[serde(default = "default_token")]
+    pub cookie: String,
+    /// Claim used as session id.
+    #[serde(default = "default_claim")]
+    pub claim: String,
+    #[serde(skip)]
+    keys: Arc<Vec<(Option<String>, DecodingKey)>>,
+}
+
+fn default_alg() -> Algorithm {
+    Algorithm::HS256
+}
+
+fn default_token() -> String {
+    "token".to_owned()
+}
+
+fn default_claim() -> String {
+    "sub".to_owned()
+}
+
+impl Jwt {
+    /// Reads the secret or JWKS file, called on every (re)load of the config.
+    pub fn load_keys(&mut self) -> Result<()> {
+        let mut keys = Vec::new();
+        if let Some(secret) = &self.secret {
+            keys.push((None, DecodingKey::from_secret(secret.as_bytes())));
+        }
+        if let Some(path) = &self.jwks {
+            let content =
+                std::fs::read_to_string(path).with_context(|| format!("jwks {}", path))?;
+            let set: JwkSet = serde_json::from_str(&content)?;
+            for k in &set.keys {
+                keys.push((k.common.key_id.clone(), DecodingKey::from_jwk(k)?));
+            }
+        }
+        if keys.is_empty() {
+            bail!("jwt auth needs `secret` or `jwks`");
+        }
+        self.keys = Arc::new(keys);
+        Ok(())
+    }
+
+    fn token<'a>(&self, q: &'a Map<String, Value>, jar: &'a CookieJar) -> Option<&'a str> {
+        q.get(&self.param)
+            .and_then(|x| x.as_str())
+            .or_else(|| jar.get(&self.cookie).map(|c| c.value()))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Map<String, Value>> {
+        let header = decode_header(token)?;
+        // Only a token without `kid` falls back to the first key, an unknown
+        // one is a rotation mistake rather than something to guess around
+        let key = match &header.kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|(k, _)| k.as_ref() == Some(kid))
+                .ok_or_else(|| anyhow!("unknown key id `{}`", kid))?,
+            None => self
+                .keys
+                .first()
+                .ok_or_else(|| anyhow!("no decoding key"))?,
+        };
+        let key = &key.1;
+        let mut validation = Validation::new(self.alg);
+        if let Some(iss) = &self.issuer {
+            validation.set_issuer(&[iss]);
+        }
+        match &self.audience {
+            Some(aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+        Ok(decode::<Map<String, Value>>(token, key, &validation)?.claims)
+    }
+
+    /// Builds the session from the token claims. The login hook is only called
+    /// the first time a subject is seen, its answer is remembered in `known`;
+    /// when it fails the claims alone are used.
+    pub async fn login(
+        &self,
+        q: &Map<String, Value>,
+        jar: &CookieJar,
+        hook: &Hook,
+        known: &DashMap<Session, Info>,
+        tmpls: Arc<Tmpls<'_>>,
+    ) -> Result<SessionInfo> {
+        let token = self.token(q, jar).ok_or_else(|| anyhow!("missing token"))?;
+        let claims = self.verify(token)?;
+        let id: Session = claims
+            .get(&self.claim)
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| anyhow!("missing claim `{}`", self.claim))?
+            .into();
+
+        if let Some(info) = known.get(&id) {
+            return Ok(SessionInfo {
+                id,
+                info: info.clone(),
+            });
+        }
+
+        // The hook gets the claims, not the bearer token itself
+        let mut ctx = q.clone();
+        ctx.remove(&self.param);
+        ctx.insert("claims".into(), Value::Object(claims.clone()));
+        let info = match hook.handle::<SessionInfo>(&ctx, tmpls).await {
+            Ok(s) => s.info,
+            Err(e) => {
+                warn!("login hook failed for {}, using claims: {:?}", id, e);
+                return Ok(SessionInfo { id, info: claims });
+            }
+        };
+        known.insert(id.clone(), info.clone());
+        Ok(SessionInfo { id, info })
+    }
+}
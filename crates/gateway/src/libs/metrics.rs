@@ -0,0 +1,220 @@
+use super::config::HookMap;
+use super::error::HttpResult;
+use super::shared::{Sender, StateChat};
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+use message::codec::CodecType;
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{counter::Counter, family::Family, gauge::Gauge, histogram::Histogram},
+    registry::Registry,
+};
+use std::time::Instant;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct CodecLabels {
+    pub codec: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MessageLabels {
+    pub event: String,
+    pub codec: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct HookLabels {
+    pub hook: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ReasonLabels {
+    pub reason: String,
+}
+
+fn hook_histogram() -> Histogram {
+    Histogram::new(prometheus_client::metrics::histogram::exponential_buckets(
+        0.005, 2.0, 12,
+    ))
+}
+
+fn codec_name(codec: CodecType) -> String {
+    codec.name().to_owned()
+}
+
+/// Clients pick the event names, only those with a configured hook get a
+/// label of their own, the rest are counted as `other`.
+pub fn event_label(hooks: &HookMap, event: Option<&str>) -> String {
+    match event {
+        Some(ev) if hooks.contains_key(ev) => ev.to_owned(),
+        _ => "other".to_owned(),
+    }
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    pub sessions: Gauge,
+    pub opened: Family<CodecLabels, Counter>,
+    pub closed: Family<CodecLabels, Counter>,
+    pub inbound: Family<MessageLabels, Counter>,
+    pub outbound: Family<MessageLabels, Counter>,
+    pub hook_seconds: Family<HookLabels, Histogram, fn() -> Histogram>,
+    pub hook_errors: Family<HookLabels, Counter>,
+    pub queue_sent: Counter,
+    pub queue_send_errors: Counter,
+    pub queue_received: Counter,
+    pub dropped: Family<ReasonLabels, Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("gateway");
+        let m = Self {
+            registry: Registry::default(),
+            sessions: Gauge::default(),
+            opened: Family::default(),
+            closed: Family::default(),
+            inbound: Family::default(),
+            outbound: Family::default(),
+            hook_seconds: Family::new_with_constructor(hook_histogram),
+            hook_errors: Family::default(),
+            queue_sent: Counter::default(),
+            queue_send_errors: Counter::default(),
+            queue_received: Counter::default(),
+            dropped: Family::default(),
+        };
+        registry.register("sessions", "Active WS sessions", m.sessions.clone());
+        registry.register(
+            "connections_opened",
+            "WS connections opened",
+            m.opened.clone(),
+        );
+        registry.register(
+            "connections_closed",
+            "WS connections closed",
+            m.closed.clone(),
+        );
+        registry.register(
+            "messages_inbound",
+            "Messages received from clients",
+            m.inbound.clone(),
+        );
+        registry.register(
+            "messages_outbound",
+            "Messages sent to clients",
+            m.outbound.clone(),
+        );
+        registry.register(
+            "hook_duration_seconds",
+            "Hook latency",
+            m.hook_seconds.clone(),
+        );
+        registry.register("hook_errors", "Failed hook calls", m.hook_errors.clone());
+        registry.register(
+            "queue_sent",
+            "Messages handed to the outgo queue",
+            m.queue_sent.clone(),
+        );
+        registry.register(
+            "queue_send_errors",
+            "Messages the outgo queue refused",
+            m.queue_send_errors.clone(),
+        );
+        registry.register(
+            "queue_received",
+            "Messages received from the income queue",
+            m.queue_received.clone(),
+        );
+        registry.register(
+            "messages_dropped",
+            "Messages not delivered",
+            m.dropped.clone(),
+        );
+        Self { registry, ..m }
+    }
+}
+
+impl Metrics {
+    pub fn opened(&self, codec: CodecType) {
+        self.opened
+            .get_or_create(&CodecLabels {
+                codec: codec_name(codec),
+            })
+            .inc();
+    }
+
+    pub fn closed(&self, codec: CodecType) {
+        self.closed
+            .get_or_create(&CodecLabels {
+                codec: codec_name(codec),
+            })
+            .inc();
+    }
+
+    /// `event` as given by [`event_label`].
+    pub fn inbound(&self, event: String, codec: CodecType) {
+        self.inbound
+            .get_or_create(&MessageLabels {
+                event,
+                codec: codec_name(codec),
+            })
+            .inc();
+    }
+
+    /// `event` as given by [`event_label`].
+    pub fn outbound(&self, event: String, codec: CodecType) {
+        self.outbound
+            .get_or_create(&MessageLabels {
+                event,
+                codec: codec_name(codec),
+            })
+            .inc();
+    }
+
+    /// Records the latency of a hook call started at `start`, and its failure if any.
+    pub fn hook<T, E>(&self, hook: &str, start: Instant, r: &Result<T, E>) {
+        let labels = HookLabels {
+            hook: hook.to_owned(),
+        };
+        self.hook_seconds
+            .get_or_create(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        if r.is_err() {
+            self.hook_errors.get_or_create(&labels).inc();
+        }
+    }
+
+    pub fn queue_send<T, E>(&self, r: &Result<T, E>) {
+        match r {
+            Ok(_) => self.queue_sent.inc(),
+            Err(_) => self.queue_send_errors.inc(),
+        };
+    }
+
+    pub fn dropped(&self, reason: &str) {
+        self.dropped
+            .get_or_create(&ReasonLabels {
+                reason: reason.to_owned(),
+            })
+            .inc();
+    }
+
+    pub fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut buf = String::new();
+        encode(&mut buf, &self.registry)?;
+        Ok(buf)
+    }
+}
+
+/// OpenMetrics exposition, session gauge is sampled at scrape time.
+pub async fn metrics(State(state): State<StateChat<Sender>>) -> HttpResult<impl IntoResponse> {
+    state.metrics.sessions.set(state.session.len() as i64);
+    let body = state.metrics.encode()?;
+    Ok((
+        [(
+            CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        body,
+    ))
+}
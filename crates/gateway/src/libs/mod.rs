@@ -1,7 +1,15 @@
 pub mod admin;
+pub mod auth;
 pub mod config;
+pub mod delta;
 pub mod error;
+pub mod jwt;
+pub mod metrics;
+pub mod record;
 pub mod shared;
+pub mod sse;
+pub mod store;
+pub mod tap;
 pub mod template;
 pub mod webhooks;
 pub mod websocket;
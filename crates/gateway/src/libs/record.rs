@@ -0,0 +1,68 @@
+use super::tap::Tap;
+use message::glob;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+/// `[record]` section: append the traffic of matching sessions to
+/// `{dir}/{session}.jsonl`, to be replayed with the `replay` binary.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Record {
+    pub dir: String,
+    /// Glob on the session id, every session when absent.
+    pub session: Option<String>,
+}
+
+impl Record {
+    /// Subscribes to the tap, so recording costs nothing on the hot path
+    /// beyond what the admin tap already does.
+    pub fn spawn(&self, tap: &Tap) {
+        let mut rx = tap.subscribe();
+        let dir = PathBuf::from(&self.dir);
+        let filter = self.session.clone();
+        info!("Recording sessions to {}", dir.display());
+        tokio::spawn(async move {
+            if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+                error!("record dir {}: {}", dir.display(), e);
+                return;
+            }
+            loop {
+                let e = match rx.recv().await {
+                    Ok(e) => e,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("recorder lagged, {} messages not recorded", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if filter
+                    .as_ref()
+                    .is_some_and(|p| !glob(p.as_bytes(), e.session.as_bytes()))
+                {
+                    continue;
+                }
+                let Ok(mut line) = serde_json::to_vec(&e) else {
+                    continue;
+                };
+                line.push(b'\n');
+                // Session ids come from the login hook, keep them out of the path
+                let name = e.session.replace(['/', '\\', '.'], "_");
+                let path = dir.join(format!("{}.jsonl", name));
+                let r = async {
+                    tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .await?
+                        .write_all(&line)
+                        .await
+                };
+                if let Err(e) = r.await {
+                    error!("record {}: {}", path.display(), e);
+                }
+            }
+        });
+    }
+}
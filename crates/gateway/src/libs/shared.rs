@@ -1,4 +1,8 @@
-use super::config::Config;
+use super::config::{Config, LiveConfig};
+use super::delta::Baselines;
+use super::metrics::Metrics;
+use super::tap::Tap;
+use super::template::Tmpls;
 use arc_swap::ArcSwap;
 use axum::extract::FromRef;
 use dashmap::{
@@ -8,33 +12,44 @@ use dashmap::{
 };
 use message::{
     ChatMessage,
-    codec::{ActiveCodec, CodecType},
+    codec::{CodecType, Protocol},
     session::{Session, SessionCount},
     time::Created,
 };
 use serde_json::{Map, Value};
-use std::{fmt::Debug, ops::Deref, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    net::SocketAddr,
+    ops::Deref,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 use time::OffsetDateTime;
 use tokio::sync::{RwLock, mpsc::UnboundedSender};
 
-/// Encode a value and wrap it in the appropriate WS frame type (Text/JSON vs Binary/CBOR).
+/// Encode a value and wrap it in the appropriate WS frame type (Text/JSON vs Binary/CBOR or compressed).
 pub fn encode_ws<T: serde::Serialize>(
-    codec: CodecType,
+    protocol: Protocol,
     value: &T,
 ) -> Option<axum::extract::ws::Message> {
-    let bytes = ActiveCodec::new(codec).encode(value).ok()?;
-    Some(match codec {
-        CodecType::Json => {
-            let s = String::from_utf8(bytes).unwrap_or_default();
-            axum::extract::ws::Message::Text(s.into())
-        }
-        CodecType::Cbor => axum::extract::ws::Message::Binary(bytes.into()),
+    let bytes = protocol.encode(value).ok()?;
+    Some(if protocol.is_binary() {
+        axum::extract::ws::Message::Binary(bytes.into())
+    } else {
+        let s = String::from_utf8(bytes).unwrap_or_default();
+        axum::extract::ws::Message::Text(s.into())
     })
 }
 
 #[derive(Clone, Debug)]
 pub struct SessionManager<T> {
     map: DashMap<Session, T>,
+    /// Outlive the entries in `map`, see `delta::Delta`.
+    baselines: Baselines,
 }
 
 impl<'a, T> IntoIterator for &'a SessionManager<T> {
@@ -49,9 +64,15 @@ impl<T> SessionManager<T> {
     fn new() -> Self {
         Self {
             map: DashMap::new(),
+            baselines: Baselines::default(),
         }
     }
 
+    /// Last layout sent to each session, connected or not.
+    pub fn baselines(&self) -> &Baselines {
+        &self.baselines
+    }
+
     pub fn get(&self, k: &Session) -> Option<Ref<'_, Session, T>> {
         self.map.get(k)
     }
@@ -64,6 +85,22 @@ impl<T> SessionManager<T> {
         self.map.remove(k)
     }
 
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn remove_if(
+        &self,
+        k: &Session,
+        f: impl FnOnce(&Session, &T) -> bool,
+    ) -> Option<(Session, T)> {
+        self.map.remove_if(k, f)
+    }
+
     pub fn contains_key(&self, k: &Session) -> bool {
         self.map.contains_key(k)
     }
@@ -84,6 +121,27 @@ pub struct Shared<T> {
     pub session: Arc<SessionManager<T>>,
     pub count: Arw<SessionCount>,
     pub config: Arc<ArcSwap<Config>>,
+    pub tmpls: Arc<ArcSwap<Tmpls<'static>>>,
+    pub live: LiveConfig,
+    /// Set on shutdown, new upgrades are refused from then on.
+    pub closing: Arc<AtomicBool>,
+    conns: Arc<AtomicUsize>,
+    /// Session info returned by the login hook, per subject connected with a
+    /// JWT. Dropped at logout.
+    pub known: Arc<DashMap<Session, Info>>,
+    pub metrics: Arc<Metrics>,
+    pub tap: Tap,
+    /// Upstream token of each `/events` stream.
+    pub streams: Arc<DashMap<String, Session>>,
+}
+
+/// Held for the whole lifetime of a WS connection, including its logout hook.
+pub struct ConnGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl<T: Clone> FromRef<Shared<T>> for Arc<SessionManager<T>> {
@@ -104,12 +162,64 @@ impl<T> FromRef<Shared<T>> for Arc<ArcSwap<Config>> {
     }
 }
 
+impl<T> FromRef<Shared<T>> for Arc<ArcSwap<Tmpls<'static>>> {
+    fn from_ref(input: &Shared<T>) -> Self {
+        input.tmpls.clone()
+    }
+}
+
+impl<T> FromRef<Shared<T>> for LiveConfig {
+    fn from_ref(input: &Shared<T>) -> Self {
+        input.live.clone()
+    }
+}
+
 impl<T> Shared<T> {
-    pub fn new(config: Arc<ArcSwap<Config>>) -> Self {
+    pub fn new(live: &LiveConfig) -> Self {
         Shared {
             session: Arc::new(SessionManager::new()),
             count: Arc::new(RwLock::new(SessionCount::default())),
-            config,
+            config: live.data.clone(),
+            tmpls: live.tmpls.clone(),
+            live: live.clone(),
+            closing: Arc::new(AtomicBool::new(false)),
+            conns: Arc::new(AtomicUsize::new(0)),
+            known: Arc::new(DashMap::new()),
+            metrics: Arc::new(Metrics::default()),
+            tap: Tap::default(),
+            streams: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn track(&self) -> ConnGuard {
+        self.conns.fetch_add(1, Ordering::SeqCst);
+        ConnGuard(self.conns.clone())
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Shared<Client<T>> {
+    /// Refuses new upgrades, asks every session to close and waits up to `grace`
+    /// for the connections (and their logout hooks) to finish.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.closing.store(true, Ordering::SeqCst);
+        let mut terms = Vec::new();
+        for x in &*self.session {
+            terms.push(x.value().term.clone());
+        }
+        for t in terms {
+            let _ = t.send(true).await;
+        }
+        let deadline = tokio::time::Instant::now() + grace;
+        while self.conns.load(Ordering::SeqCst) > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let left = self.conns.load(Ordering::SeqCst);
+        if left > 0 {
+            tracing::warn!("shutdown: {} connections still open", left);
         }
     }
 }
@@ -122,8 +232,66 @@ pub struct Client<T> {
     pub term: tokio::sync::mpsc::Sender<bool>,
     pub created: OffsetDateTime,
     pub info: Info,
-    /// Codec determined at handshake from URL query parameter.
+    /// Codec determined at handshake from the subprotocol or URL query parameter.
     pub codec: CodecType,
+    pub protocol: Protocol,
+    pub remote: SocketAddr,
+    pub stats: Arc<Stats>,
+}
+
+/// Traffic of one connection, for the admin API.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    tail: usize,
+    recent: Mutex<VecDeque<Delivered>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Delivered {
+    #[serde(with = "time::serde::rfc3339")]
+    pub sent: OffsetDateTime,
+    pub message: Value,
+}
+
+impl Stats {
+    pub fn new(tail: usize) -> Self {
+        Self {
+            tail,
+            ..Default::default()
+        }
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a frame sent to the client and keeps the message in the tail.
+    pub fn delivered(&self, bytes: usize, message: Value) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        if self.tail == 0 {
+            return;
+        }
+        let mut recent = self.recent.lock().expect("stats lock poisoned");
+        if recent.len() >= self.tail {
+            recent.pop_front();
+        }
+        recent.push_back(Delivered {
+            sent: OffsetDateTime::now_utc(),
+            message,
+        });
+    }
+
+    /// Last `n` delivered messages, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<Delivered> {
+        let recent = self.recent.lock().expect("stats lock poisoned");
+        recent
+            .iter()
+            .skip(recent.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
 }
 
 impl<T> Deref for Client<T> {
@@ -0,0 +1,209 @@
+//! Fallback transport for clients behind proxies that break WebSockets:
+//! `GET /events` streams the messages as Server-Sent Events, `POST /events`
+//! carries what the client would have sent on the socket.
+use super::auth::{login, logout, logout_hook};
+use super::config::Hook;
+use super::shared::{Client, ConnGuard, Sender, StateChat, Stats};
+use super::websocket::{Inbound, Outbound, decode_frame, greet, register};
+use axum::{
+    Extension,
+    body::Bytes,
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use axum_extra::extract::cookie::CookieJar;
+use futures::stream::{self, Stream, StreamExt};
+use message::{
+    ChatMessage,
+    codec::{CodecType, Protocol},
+    session::{Session, SessionInfo},
+    time::Created,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use short_uuid::ShortUuid;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver, channel, unbounded_channel};
+
+/// Header carrying the upstream token, also accepted as `?token=`.
+const TOKEN_HEADER: &str = "x-stream-token";
+
+/// Cleans up when the client goes away and the response stream is dropped.
+struct Closer {
+    state: StateChat<Sender>,
+    session: SessionInfo,
+    token: String,
+    sender: Sender,
+    logout: Hook,
+    guard: Option<ConnGuard>,
+}
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        tracing::info!("Stream closed for {}", &self.session.id);
+        self.state.streams.remove(&self.token);
+        self.state.metrics.closed(CodecType::Json);
+        // A newer connection of the same session may have replaced this one
+        self.state
+            .session
+            .remove_if(&self.session.id, |_, c| c.sender.same_channel(&self.sender));
+        let state = self.state.clone();
+        let session = self.session.clone();
+        let logout_hook = self.logout.clone();
+        let guard = self.guard.take();
+        tokio::spawn(async move {
+            let _guard = guard;
+            logout(&state, &logout_hook, session).await;
+        });
+    }
+}
+
+struct Downstream {
+    rx: UnboundedReceiver<ChatMessage<Created>>,
+    term: Receiver<bool>,
+    outbound: Outbound,
+    _closer: Closer,
+}
+
+fn events(first: Event, down: Downstream) -> impl Stream<Item = Result<Event, Infallible>> {
+    let rest = stream::unfold(down, |mut down| async move {
+        let msg = tokio::select! {
+            Some(msg) = down.rx.recv() => msg,
+            _ = down.term.recv() => return None,
+        };
+        let data = serde_json::to_string(&msg).unwrap_or_default();
+        down.outbound.sent(&msg, data.len());
+        Some((Ok(Event::default().data(data)), down))
+    });
+    stream::once(async move { Ok(first) }).chain(rest)
+}
+
+pub async fn subscribe(
+    Query(mut q): Query<Map<String, Value>>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<StateChat<Sender>>,
+) -> Response {
+    if state.is_closing() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "SHUTTING DOWN").into_response();
+    }
+    let Some(a) = login(&state, &mut q, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
+    };
+    let Some(logout_hook) = logout_hook(&state) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "NO LOGOUT HOOK").into_response();
+    };
+    let guard = state.track();
+
+    // SSE is text only, the stream is always JSON
+    let codec = CodecType::Json;
+    let (tx, rx) = unbounded_channel();
+    let (term_tx, term_rx) = channel(1);
+    let stats = Arc::new(Stats::new(state.config.load().admin.tail));
+    register(
+        &state,
+        &a.id,
+        Client {
+            sender: tx.clone(),
+            term: term_tx,
+            info: a.info.clone(),
+            created: OffsetDateTime::now_utc(),
+            codec,
+            protocol: Protocol::new(codec),
+            remote: addr,
+            stats: stats.clone(),
+        },
+    )
+    .await;
+    state.metrics.opened(codec);
+    // Layouts go out in full here, a later socket cannot patch against them
+    state.session.baselines().forget(&a.id);
+
+    let token = ShortUuid::generate().to_string();
+    state.streams.insert(token.clone(), a.id.clone());
+
+    for payload in greet::<ChatMessage<Created>, _>(&state, &a).await {
+        let _ = tx.send(payload);
+    }
+
+    let first = Event::default()
+        .event("session")
+        .data(json!({ "session": a.id, "token": token }).to_string());
+    let down = Downstream {
+        rx,
+        term: term_rx,
+        outbound: Outbound::new(&state, &a.id, codec, &stats),
+        _closer: Closer {
+            state: state.clone(),
+            session: a,
+            token,
+            sender: tx,
+            logout: logout_hook,
+            guard: Some(guard),
+        },
+    };
+    Sse::new(events(first, down))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Upstream {
+    token: Option<String>,
+}
+
+/// One client message per request, JSON unless sent as `application/cbor`,
+/// `application/msgpack` or `application/x-postcard`.
+pub async fn publish(
+    Query(up): Query<Upstream>,
+    headers: HeaderMap,
+    State(state): State<StateChat<Sender>>,
+    Extension(outgo_tx): Extension<Sender>,
+    body: Bytes,
+) -> Response {
+    let token = up.token.or_else(|| {
+        headers
+            .get(TOKEN_HEADER)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_owned())
+    });
+    let Some(sid) = token.and_then(|t| state.streams.get(&t).map(|x| x.value().clone())) else {
+        return (StatusCode::NOT_FOUND, "UNKNOWN STREAM").into_response();
+    };
+    let Some((tx, stats)) = client(&state, &sid) else {
+        return (StatusCode::NOT_FOUND, "UNKNOWN STREAM").into_response();
+    };
+    stats.received(body.len());
+    let codec = headers
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("application/"))
+        .and_then(|x| x.split(';').next()?.trim_start_matches("x-").parse().ok())
+        .unwrap_or(CodecType::Json);
+    let value = match decode_frame(&body, codec != CodecType::Json, Protocol::new(codec)) {
+        Ok(v) => v,
+        Err(e) => {
+            state.metrics.dropped("decode");
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    let inbound = Inbound::new(&state, &sid, Protocol::new(codec), tx, outgo_tx);
+    match inbound.handle(value).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+fn client(state: &StateChat<Sender>, sid: &Session) -> Option<(Sender, Arc<Stats>)> {
+    state
+        .session
+        .get(sid)
+        .map(|c| (c.sender.clone(), c.stats.clone()))
+}
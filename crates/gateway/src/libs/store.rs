@@ -0,0 +1,252 @@
+use super::config::{HookMap, Hooks};
+use anyhow::Result;
+use indexmap::IndexMap;
+use indoc::indoc;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, from_value, to_value};
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls, connect};
+
+/// Where hooks registered through `/config/hooks` are kept across restarts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum StoreConfig {
+    #[allow(non_camel_case_types)]
+    file { path: String },
+    #[allow(non_camel_case_types)]
+    postgres { url: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction {
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "delete")]
+    Delete,
+}
+
+impl ChangeAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookChange {
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+    pub actor: String,
+    pub action: ChangeAction,
+    pub hook: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Hooks>,
+}
+
+/// Latest runtime change per hook, `None` marks a deleted hook.
+pub type HookOverlay = IndexMap<String, Option<Hooks>>;
+
+pub fn apply_overlay(hooks: &mut HookMap, overlay: &HookOverlay) {
+    for (k, v) in overlay {
+        match v {
+            Some(v) => {
+                hooks.insert(k.clone(), v.clone());
+            }
+            None => {
+                hooks.shift_remove(k);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    hooks: HookOverlay,
+    history: Vec<HookChange>,
+}
+
+impl Snapshot {
+    fn push(&mut self, change: HookChange) {
+        self.hooks.insert(change.hook.clone(), change.value.clone());
+        self.history.push(change);
+    }
+
+    fn history(&self, hook: Option<&str>, limit: usize) -> Vec<HookChange> {
+        self.history
+            .iter()
+            .rev()
+            .filter(|x| hook.is_none_or(|h| x.hook == h))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigStore {
+    /// No persistence configured, changes and history live until restart.
+    Memory(Mutex<Snapshot>),
+    File {
+        path: PathBuf,
+        snapshot: Mutex<Snapshot>,
+    },
+    Postgres(Client),
+}
+
+impl ConfigStore {
+    pub async fn new(config: Option<&StoreConfig>) -> Result<Self> {
+        match config {
+            None => Ok(Self::Memory(Mutex::new(Snapshot::default()))),
+            Some(StoreConfig::file { path }) => {
+                let path = PathBuf::from(path);
+                let snapshot = match async_fs::read_to_string(&path).await {
+                    Ok(s) => serde_json::from_str(&s)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Snapshot::default(),
+                    Err(e) => return Err(e.into()),
+                };
+                Ok(Self::File {
+                    path,
+                    snapshot: Mutex::new(snapshot),
+                })
+            }
+            Some(StoreConfig::postgres { url }) => {
+                let (client, conn) = connect(url, NoTls).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = conn.await {
+                        tracing::error!("config store connection error: {}", e);
+                    }
+                });
+                client
+                    .batch_execute(indoc! {
+                        "
+                        create table if not exists gateway_hook_history (
+                            id bigserial primary key,
+                            created timestamptz not null default now(),
+                            actor text not null,
+                            action text not null,
+                            hook text not null,
+                            value jsonb
+                        );
+                        create index if not exists gateway_hook_history_hook
+                            on gateway_hook_history(hook);
+                        "
+                    })
+                    .await?;
+                Ok(Self::Postgres(client))
+            }
+        }
+    }
+
+    pub async fn overlay(&self) -> Result<HookOverlay> {
+        match self {
+            Self::Memory(s) | Self::File { snapshot: s, .. } => Ok(s.lock().await.hooks.clone()),
+            Self::Postgres(client) => {
+                let rows = client
+                    .query(
+                        indoc! {
+                            "
+                            select distinct on (hook) hook, value
+                            from gateway_hook_history
+                            order by hook, id desc
+                            "
+                        },
+                        &[],
+                    )
+                    .await?;
+                let mut r = HookOverlay::new();
+                for row in rows {
+                    let value: Option<Value> = row.try_get("value")?;
+                    r.insert(row.try_get("hook")?, value.map(from_value).transpose()?);
+                }
+                Ok(r)
+            }
+        }
+    }
+
+    pub async fn record(&self, change: HookChange) -> Result<()> {
+        match self {
+            Self::Memory(s) => {
+                s.lock().await.push(change);
+            }
+            Self::File { path, snapshot } => {
+                let mut s = snapshot.lock().await;
+                let mut next = s.clone();
+                next.push(change);
+                if let Some(dir) = path.parent() {
+                    async_fs::create_dir_all(dir).await?;
+                }
+                // Write aside and rename, so a crash never leaves a truncated snapshot,
+                // and keep the snapshot in step with the file when either fails
+                let tmp = path.with_extension("tmp");
+                async_fs::write(&tmp, serde_json::to_vec_pretty(&next)?).await?;
+                async_fs::rename(&tmp, path).await?;
+                *s = next;
+            }
+            Self::Postgres(client) => {
+                let value = change.value.as_ref().map(to_value).transpose()?;
+                client
+                    .execute(
+                        indoc! {
+                            "
+                            insert into gateway_hook_history (created, actor, action, hook, value)
+                            values ($1, $2, $3, $4, $5)
+                            "
+                        },
+                        &[
+                            &change.created,
+                            &change.actor,
+                            &change.action.as_str(),
+                            &change.hook,
+                            &value,
+                        ],
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Most recent changes first.
+    pub async fn history(&self, hook: Option<&str>, limit: usize) -> Result<Vec<HookChange>> {
+        match self {
+            Self::Memory(s) | Self::File { snapshot: s, .. } => {
+                Ok(s.lock().await.history(hook, limit))
+            }
+            Self::Postgres(client) => {
+                let rows = client
+                    .query(
+                        indoc! {
+                            "
+                            select created, actor, action, hook, value
+                            from gateway_hook_history
+                            where $1::text is null or hook = $1
+                            order by id desc
+                            limit $2
+                            "
+                        },
+                        &[&hook, &(limit as i64)],
+                    )
+                    .await?;
+                let mut r = Vec::with_capacity(rows.len());
+                for row in rows {
+                    let value: Option<Value> = row.try_get("value")?;
+                    r.push(HookChange {
+                        created: row.try_get("created")?,
+                        actor: row.try_get("actor")?,
+                        action: match row.try_get::<_, &str>("action")? {
+                            "delete" => ChangeAction::Delete,
+                            _ => ChangeAction::Update,
+                        },
+                        hook: row.try_get("hook")?,
+                        value: value.map(from_value).transpose()?,
+                    });
+                }
+                Ok(r)
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::shared::{Sender, StateChat};
+use axum::{
+    extract::{
+        Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+pub use message::record::{Direction, Entry as TapEvent};
+use message::{glob, session::Session};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Buffered events per subscriber, slower ones get a `lagged` notice.
+const CAPACITY: usize = 1024;
+
+/// Copies every message crossing the gateway to the admin subscribers.
+#[derive(Debug, Clone)]
+pub struct Tap(broadcast::Sender<TapEvent>);
+
+impl Default for Tap {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Tap {
+    /// Whether anyone listens, so callers can skip serializing the message.
+    pub fn is_active(&self) -> bool {
+        self.0.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TapEvent> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, direction: Direction, session: &Session, message: Value) {
+        if !self.is_active() {
+            return;
+        }
+        let _ = self
+            .0
+            .send(TapEvent::new(direction, session.clone(), message));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Filter {
+    session: Option<Session>,
+    /// Glob on the event name, `*` and `?` wildcards.
+    event: Option<String>,
+    direction: Option<Direction>,
+}
+
+impl Filter {
+    fn matches(&self, e: &TapEvent) -> bool {
+        self.session.as_ref().is_none_or(|s| *s == e.session)
+            && self.direction.is_none_or(|d| d == e.direction)
+            && self.event.as_ref().is_none_or(|p| {
+                e.event
+                    .as_deref()
+                    .is_some_and(|ev| glob(p.as_bytes(), ev.as_bytes()))
+            })
+    }
+}
+
+pub async fn tap(
+    ws: WebSocketUpgrade,
+    Query(filter): Query<Filter>,
+    State(state): State<StateChat<Sender>>,
+) -> Response {
+    let rx = state.tap.subscribe();
+    ws.on_upgrade(move |socket| stream(socket, rx, filter))
+}
+
+async fn stream(mut socket: WebSocket, mut rx: broadcast::Receiver<TapEvent>, filter: Filter) {
+    loop {
+        tokio::select! {
+            e = rx.recv() => {
+                let text = match e {
+                    Ok(e) if filter.matches(&e) => match serde_json::to_string(&e) {
+                        Ok(t) => t,
+                        Err(_) => continue,
+                    },
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(n)) => json!({ "lagged": n }).to_string(),
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            },
+            m = socket.recv() => match m {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
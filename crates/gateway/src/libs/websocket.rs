@@ -1,26 +1,33 @@
-use super::config::{Config, Hook};
-use super::shared::{Client, StateChat, encode_ws};
+use super::config::{Config, Hook, HookMap};
+use super::delta::Delta;
+use super::metrics::{Metrics, event_label};
+use super::shared::{Client, StateChat, Stats};
+use super::tap::{Direction, Tap};
 use super::template::Tmpls;
-use anyhow::{Ok as Okk, Result};
+use anyhow::{Ok as Okk, Result, bail};
 use arc_swap::ArcSwap;
-use axum::extract::ws::WebSocket;
+use axum::extract::ws::{Message, WebSocket};
 use dashmap::Entry;
 use futures::{sink::SinkExt, stream::StreamExt};
-use message::codec::{ActiveCodec, CodecType};
+use message::codec::{CodecType, Protocol};
 use message::{
     Event,
     session::{Session, SessionInfo},
     time::Created,
+    trace::TraceContext,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::{Map, Value, to_value};
+use serde_json::{Map, Value, json, to_value};
 use std::fmt::Debug;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 use time::OffsetDateTime;
 use tokio::sync::{
     Mutex,
     mpsc::{UnboundedReceiver, UnboundedSender},
 };
+use tracing::{Instrument, Span, field::Empty, info_span};
 
 impl Hook {
     async fn greet<T>(&self, context: &Map<String, Value>, tmpls: Arc<Tmpls<'_>>) -> Result<T>
@@ -33,14 +40,256 @@ impl Hook {
     }
 }
 
+fn frame_len(msg: &Message) -> usize {
+    match msg {
+        Message::Text(t) => t.len(),
+        Message::Binary(b) => b.len(),
+        _ => 0,
+    }
+}
+
+/// Decodes a client frame with the codec negotiated at handshake, a frame of
+/// the wrong type is a client error rather than a hint to switch codec.
+pub fn decode_frame(bytes: &[u8], binary: bool, protocol: Protocol) -> Result<Value> {
+    if binary != protocol.is_binary() {
+        bail!(
+            "{} expects {} frames, got a {} frame",
+            protocol,
+            if protocol.is_binary() {
+                "binary"
+            } else {
+                "text"
+            },
+            if binary { "binary" } else { "text" },
+        );
+    }
+    Ok(protocol.decode(bytes)?)
+}
+
+/// Replaces a previous connection of the same session, which is told to terminate.
+pub async fn register<T>(state: &StateChat<T>, id: &Session, client: Client<T>) {
+    match state.session.entry(id.clone()) {
+        Entry::Occupied(mut e) => {
+            let g = e.get_mut();
+            let _ = g.term.send(true).await;
+            *g = client;
+            // The upstream token of a replaced `/events` stream must not post
+            // into this connection
+            state.streams.retain(|_, s| s != id);
+        }
+        Entry::Vacant(e) => {
+            e.insert(client);
+        }
+    }
+    tracing::info!("Connection opened for {}", id);
+}
+
+/// Payloads of the `greet` hooks for a new session.
+pub async fn greet<T, S>(state: &StateChat<S>, session: &SessionInfo) -> Vec<T>
+where
+    T: Event<Created> + Serialize + From<(Session, Value)>,
+{
+    let mut context = Map::new();
+    context.insert("session_id".into(), session.id.clone().into());
+    context.insert("info".into(), Value::Object(session.info.clone()));
+
+    let mut r = Vec::new();
+    let config = state.config.load_full();
+    let Some(greets) = config.hooks.get("greet") else {
+        return r;
+    };
+    for g in greets.iter() {
+        let start = Instant::now();
+        let x = g
+            .greet::<T>(&context, state.tmpls.load_full())
+            .instrument(info_span!("hook", hook = "greet"))
+            .await;
+        state.metrics.hook("greet", start, &x);
+        match x {
+            Ok(payload) => r.push(payload),
+            Err(e) => tracing::error!("GreetError => {:?}", e),
+        }
+    }
+    r
+}
+
+/// Accounting of a message delivered to a client, whatever the transport.
+#[derive(Clone)]
+pub struct Outbound {
+    sid: Session,
+    codec: CodecType,
+    /// Bounds the event label of the metrics.
+    hooks: HookMap,
+    metrics: Arc<Metrics>,
+    tap: Tap,
+    stats: Arc<Stats>,
+}
+
+impl Outbound {
+    pub fn new<S>(
+        state: &StateChat<S>,
+        sid: &Session,
+        codec: CodecType,
+        stats: &Arc<Stats>,
+    ) -> Self {
+        Self {
+            sid: sid.clone(),
+            codec,
+            hooks: state.config.load().hooks.clone(),
+            metrics: state.metrics.clone(),
+            tap: state.tap.clone(),
+            stats: stats.clone(),
+        }
+    }
+
+    pub fn sent<T: Event<Created> + Serialize>(&self, msg: &T, len: usize) {
+        self.metrics
+            .outbound(event_label(&self.hooks, msg.event()), self.codec);
+        let value = to_value(msg).unwrap_or_default();
+        self.tap
+            .publish(Direction::Outbound, &self.sid, value.clone());
+        self.stats.delivered(len, value);
+    }
+}
+
+/// Routes a decoded client message to its event hooks, or to the outgo queue
+/// when no hook is registered for it. Shared by every transport.
+pub struct Inbound<T> {
+    sid: Session,
+    protocol: Protocol,
+    hooks: HookMap,
+    /// Back to the client, for hook answers.
+    tx: UnboundedSender<T>,
+    outgo_tx: UnboundedSender<T>,
+    tmpls: Arc<ArcSwap<Tmpls<'static>>>,
+    metrics: Arc<Metrics>,
+    tap: Tap,
+}
+
+impl<T> Inbound<T>
+where
+    T: Event<Created>
+        + for<'a> Deserialize<'a>
+        + Serialize
+        + From<(Session, Value)>
+        + Clone
+        + Debug,
+{
+    pub fn new<S>(
+        state: &StateChat<S>,
+        sid: &Session,
+        protocol: Protocol,
+        tx: UnboundedSender<T>,
+        outgo_tx: UnboundedSender<T>,
+    ) -> Self {
+        Self {
+            sid: sid.clone(),
+            protocol,
+            hooks: state.config.load().hooks.clone(),
+            tx,
+            outgo_tx,
+            tmpls: state.tmpls.clone(),
+            metrics: state.metrics.clone(),
+            tap: state.tap.clone(),
+        }
+    }
+
+    /// Tells the client why its message was dropped, in its own codec.
+    pub fn reject(&self, code: &str, e: &anyhow::Error) {
+        // `content::Content::Error` on the client side
+        let fault = json!({ "action": "error", "code": code, "message": e.to_string() });
+        let msg: T = (self.sid.clone(), fault).into();
+        let _ = self.tx.send(msg);
+    }
+
+    pub async fn handle(&self, value: Value) -> Result<()> {
+        let sid = &self.sid;
+        let metrics = &self.metrics;
+        let span = info_span!("ws.inbound", session = %sid, event = Empty);
+        // Clients may start the trace themselves with a W3C `traceparent`
+        let parent = value
+            .get("trace")
+            .and_then(|x| x.as_str())
+            .map(TraceContext::new);
+        if let Some(t) = &parent {
+            t.attach(&span);
+        }
+        async {
+            let mut chat_msg: T = (sid.clone(), value).into();
+            if chat_msg.trace().is_none()
+                && let Some(t) = parent
+            {
+                chat_msg.set_trace(t);
+            }
+            chat_msg.set_version(self.protocol.version);
+            metrics.inbound(
+                event_label(&self.hooks, chat_msg.event()),
+                self.protocol.codec,
+            );
+            if self.tap.is_active() {
+                self.tap.publish(
+                    Direction::Inbound,
+                    sid,
+                    to_value(&chat_msg).unwrap_or_default(),
+                );
+            }
+            Span::current().record("event", chat_msg.event());
+
+            if let Some(ev) = chat_msg.event()
+                && self.hooks.contains_key(ev)
+                && let Some(wh) = self.hooks.get(ev)
+            {
+                for h in wh {
+                    if h.disable {
+                        continue;
+                    }
+                    let start = Instant::now();
+                    let r = h
+                        .variant
+                        .handle(to_value(&chat_msg)?)
+                        .instrument(info_span!("hook", hook = ev))
+                        .await;
+                    metrics.hook(ev, start, &r);
+                    match r {
+                        Ok(r) => {
+                            let _ = self.tx.send((sid.clone(), r).into());
+                        }
+                        Err(e) => {
+                            let mut err_ctx = Map::new();
+                            err_ctx.insert("event".into(), ev.into());
+                            err_ctx.insert("error".into(), e.to_string().into());
+                            if let Ok(t) = self
+                                .tmpls
+                                .load()
+                                .get_template("webhook_error.json")?
+                                .render(&err_ctx)
+                            {
+                                let _ = self.tx.send(serde_json::from_str(&t)?);
+                            }
+                        }
+                    }
+                }
+            } else {
+                let r = self.outgo_tx.send(chat_msg.clone());
+                metrics.queue_send(&r);
+            }
+
+            tracing::debug!("[ws] {:?}", &chat_msg);
+            Okk(())
+        }
+        .instrument(span)
+        .await
+    }
+}
+
 pub async fn handle_ws<T>(
     socket: WebSocket,
     outgo_tx: UnboundedSender<T>,
     state: StateChat<UnboundedSender<T>>,
     config: Arc<ArcSwap<Config>>,
-    tmpls: Arc<Tmpls<'static>>,
-    codec: ActiveCodec,
+    protocol: Protocol,
     session: &SessionInfo,
+    remote: SocketAddr,
 ) where
     T: Event<Created>
         + for<'a> Deserialize<'a>
@@ -51,15 +300,17 @@ pub async fn handle_ws<T>(
         + Send
         + 'static,
 {
-    let config_reader = config.load();
     let (mut sender, mut receiver) = socket.split();
 
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<T>();
     let (term_tx, mut term_rx) = tokio::sync::mpsc::channel(1);
 
     // Codec fixed at handshake time
-    let codec = codec.as_type();
-    tracing::info!("WS codec for {}: {:?}", &session.id, codec);
+    let codec = protocol.codec;
+    tracing::info!("WS protocol for {}: {}", &session.id, protocol);
+    let metrics = state.metrics.clone();
+    metrics.opened(codec);
+    let stats = Arc::new(Stats::new(config.load().admin.tail));
 
     let new_client = Client {
         sender: tx.clone(),
@@ -67,121 +318,68 @@ pub async fn handle_ws<T>(
         info: session.info.clone(),
         created: OffsetDateTime::now_utc(),
         codec,
+        protocol,
+        remote,
+        stats: stats.clone(),
     };
-    {
-        match state.session.entry(session.id.clone()) {
-            Entry::Occupied(mut e) => {
-                let g = e.get_mut();
-                let _ = g.term.send(true).await;
-                *g = new_client;
-            }
-            Entry::Vacant(e) => {
-                e.insert(new_client);
-            }
-        }
-    }
+    register(&state, &session.id, new_client).await;
 
-    tracing::info!("Connection opened for {}", &session.id);
-
-    let mut context = Map::new();
-    context.insert("session_id".into(), session.id.clone().into());
-    context.insert("info".into(), Value::Object(session.info.clone()));
+    let outbound = Outbound::new(&state, &session.id, codec, &stats);
 
     // Greet: send immediately using the codec determined from URL query parameter
-    if let Some(greets) = config_reader.hooks.get("greet") {
-        for g in greets.iter() {
-            match g.greet::<T>(&context, tmpls.clone()).await {
-                Ok(payload) => {
-                    if let Some(ws_msg) = encode_ws(codec, &payload) {
-                        let _ = sender.send(ws_msg).await;
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("GreetError => {:?}", e);
-                }
+    let delta = Delta::new(protocol, &session.id, state.session.baselines());
+    for payload in greet::<T, _>(&state, session).await {
+        if let Some(ws_msg) = delta.encode(&payload) {
+            let len = frame_len(&ws_msg);
+            if sender.send(ws_msg).await.is_ok() {
+                outbound.sent(&payload, len);
             }
         }
     }
 
-    let sid = session.id.clone();
-    let hooks = config_reader.hooks.clone();
-    drop(config_reader);
+    let inbound = Inbound::new(&state, &session.id, protocol, tx, outgo_tx);
+    let recv_metrics = metrics.clone();
 
     let mut recv_task = tokio::spawn(async move {
-        #[allow(unused_mut)]
-        let mut sid = sid;
-
+        let metrics = recv_metrics;
         while let Some(Ok(msg)) = receiver.next().await {
-            let value = match msg {
-                axum::extract::ws::Message::Text(t) => {
-                    match serde_json::from_str::<serde_json::Value>(&t) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            tracing::error!("JSON decode: {:?}", e);
-                            continue;
-                        }
-                    }
-                }
-                axum::extract::ws::Message::Binary(b) => {
-                    let mut cursor = std::io::Cursor::new(&b);
-                    match ciborium::de::from_reader::<serde_json::Value, _>(&mut cursor) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            tracing::error!("CBOR decode: {:?}", e);
-                            continue;
-                        }
-                    }
-                }
+            stats.received(frame_len(&msg));
+            let value = match &msg {
+                Message::Text(t) => decode_frame(t.as_bytes(), false, protocol),
+                Message::Binary(b) => decode_frame(b, true, protocol),
                 _ => continue,
             };
-
-            let chat_msg: T = (sid.clone(), value).into();
-
-            if let Some(ev) = chat_msg.event()
-                && hooks.contains_key(ev)
-                && let Some(wh) = hooks.get(ev)
-            {
-                for h in wh {
-                    if h.disable {
-                        continue;
-                    }
-                    match h.variant.handle(to_value(&chat_msg)?).await {
-                        Ok(r) => {
-                            let _ = tx.send((sid.clone(), r).into());
-                        }
-                        Err(e) => {
-                            let mut err_ctx = Map::new();
-                            err_ctx.insert("event".into(), ev.into());
-                            err_ctx.insert("error".into(), e.to_string().into());
-                            if let Ok(t) =
-                                tmpls.get_template("webhook_error.json")?.render(&err_ctx)
-                            {
-                                let _ = tx.send(serde_json::from_str(&t)?);
-                            }
-                        }
-                    }
+            let value = match value {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Decode: {:?}", e);
+                    metrics.dropped("decode");
+                    inbound.reject("decode", &e);
+                    continue;
                 }
-            } else {
-                let _ = outgo_tx.send(chat_msg.clone());
-            }
-
-            tracing::debug!("[ws] {:?}", &chat_msg);
+            };
+            inbound.handle(value).await?;
         }
         Okk(())
     });
 
-    let codec_for_send = codec;
     let replaced = Arc::new(Mutex::new(false));
     let r1 = replaced.clone();
+    let send_metrics = metrics.clone();
     let mut send_task = tokio::spawn(async move {
         loop {
             tokio::select! {
                 Some(msg) = rx.recv() => {
-                    if let Some(ws_msg) = encode_ws(codec_for_send, &msg) {
-                        if sender.send(ws_msg).await.is_err() {
-                            break;
-                        }
+                    let Some(ws_msg) = delta.encode(&msg) else {
+                        send_metrics.dropped("encode");
+                        continue;
+                    };
+                    let len = frame_len(&ws_msg);
+                    if sender.send(ws_msg).await.is_err() {
+                        send_metrics.dropped("ws_send");
+                        break;
                     }
+                    outbound.sent(&msg, len);
                 },
                 Some(_) = term_rx.recv() => { break; },
                 else => {}
@@ -200,6 +398,7 @@ pub async fn handle_ws<T>(
     };
 
     tracing::info!("Connection closed for {}", &session.id);
+    metrics.closed(codec);
     if !*replaced.lock().await {
         tracing::info!("Remove session: {}", &session.id);
         state.session.remove(&session.id);
@@ -217,11 +416,24 @@ pub async fn send_to_ws(
         let mut rx = income_rx.lock().await;
 
         while let Some(x) = rx.recv().await {
-            if !x.receiver.is_empty() {
-                for r in x.receiver {
-                    if shared.session.contains_key(&r) {
-                        let _ = shared.session.get(&r).map(|c| c.send(x.message.clone()));
+            shared.metrics.queue_received.inc();
+            let span = info_span!("ws.outbound", event = x.event());
+            if let Some(t) = x.trace() {
+                t.attach(&span);
+            }
+            let _enter = span.enter();
+            if x.receiver.is_empty() {
+                shared.metrics.dropped("no_receiver");
+                continue;
+            }
+            for r in x.receiver {
+                match shared.session.get(&r) {
+                    Some(c) => {
+                        if c.send(x.message.clone()).is_err() {
+                            shared.metrics.dropped("closed");
+                        }
                     }
+                    None => shared.metrics.dropped("no_session"),
                 }
             }
         }
@@ -1,24 +1,29 @@
 mod libs;
 use anyhow::{Ok as Okk, Result, bail};
-use arc_swap::ArcSwap;
 use axum::{
-    Router,
-    extract::{Query, State, ws::WebSocketUpgrade},
-    http::{HeaderMap, Response, StatusCode},
+    Extension, Router,
+    extract::{ConnectInfo, Query, State, ws::WebSocketUpgrade},
+    http::{Response, StatusCode},
+    middleware::from_fn_with_state,
     routing::get,
 };
 use axum_extra::extract::cookie::CookieJar;
+use axum_server::Handle;
 use libs::admin::*;
-use libs::config::{ASSETS_PATH, Config, LiveConfig, LogFormat};
+use libs::auth::{login, logout, logout_hook, require_admin, require_config};
+use libs::config::{LiveConfig, LogFormat};
+use libs::metrics::metrics;
 use libs::shared::{Sender, StateChat};
-use libs::template::Tmpls;
+use libs::sse::{publish, subscribe};
 use libs::websocket::{handle_ws, send_to_ws};
-use message::codec::ActiveCodec;
+use listenfd::ListenFd;
+use message::codec::{ActiveCodec, Protocol};
 use message::queue::MessageQueue;
+use message::server::{bind, serve, shutdown_signal};
 use serde_json::{Map, Value};
-use std::sync::Arc;
+use std::net::SocketAddr;
 use tower_http::services::ServeDir;
-use tracing::info;
+use tracing::{error, info, warn};
 use tracing_subscriber::{
     EnvFilter, fmt::layer, prelude::__tracing_subscriber_SubscriberExt, registry,
     util::SubscriberInitExt,
@@ -26,38 +31,48 @@ use tracing_subscriber::{
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    #[allow(unused_mut)]
-    let mut config = LiveConfig::new()?;
-    // config.listen().await.unwrap();
-    dbg!(&config.data);
-
-    let config = Config::new()?;
+    let live = LiveConfig::new().await?;
     // console_subscriber::init();
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    match &config.trace.format {
+    let trace = live.data.load().trace.clone();
+    let (otel, provider) = match &trace.otlp {
+        Some(otlp) => Some(message::trace::layer(otlp, "gateway")?),
+        None => None,
+    }
+    .unzip();
+    match trace.format {
         LogFormat::compact => {
-            registry().with(layer().compact()).with(filter).init();
+            registry()
+                .with(otel)
+                .with(layer().compact())
+                .with(filter)
+                .init();
         }
         LogFormat::json => {
-            registry().with(layer().json()).with(filter).init();
+            registry()
+                .with(otel)
+                .with(layer().json())
+                .with(filter)
+                .init();
         }
     };
 
-    let config = Arc::new(ArcSwap::from_pointee(config));
-    //dbg!(&config);
-    let tmpls: Arc<Tmpls<'static>> = Arc::new(Tmpls::new(ASSETS_PATH).unwrap());
+    if let Err(e) = live.listen() {
+        error!("config watcher failed: {:?}", e);
+    }
+    let config = live.data.clone();
 
-    let shared = StateChat::<Sender>::new(config.clone());
+    let shared = StateChat::<Sender>::new(&live);
 
     let queue = config.load().queue.clone();
 
     // Initialize codec
     let codec = ActiveCodec::new(config.load().codec);
 
-    let (outgo_tx, income_rx) = if !queue.disable {
+    let (outgo_tx, income_rx, drained) = if !queue.disable {
         queue.split().await
     } else {
-        (None, None)
+        (None, None, None)
     };
 
     let Some(rx) = income_rx else {
@@ -68,8 +83,12 @@ async fn main() -> Result<()> {
     };
 
     send_to_ws(rx, &shared).await;
+    if let Some(record) = &config.load().record {
+        record.spawn(&shared.tap);
+    }
 
     let codec_for_router = codec.clone();
+    let outgo = tx.clone();
     let app = Router::new()
         .route(
             "/channel",
@@ -77,27 +96,29 @@ async fn main() -> Result<()> {
                 |ws: WebSocketUpgrade,
                  Query(mut q): Query<Map<String, Value>>,
                  jar: CookieJar,
+                 ConnectInfo(addr): ConnectInfo<SocketAddr>,
                  State(state): State<StateChat<Sender>>| async move {
-                    let s = state.config.load();
-                    let login_with_cookie = s.login_with_cookie;
-                    let login = &s.hooks.get("login").unwrap()[0];
-
-                    if login_with_cookie {
-                        let cookie: Value = jar.iter().map(|c| (c.name(), c.value())).collect();
-                        q.insert("Cookie".to_owned(), cookie);
+                    if state.is_closing() {
+                        return Response::builder()
+                            .status(StatusCode::SERVICE_UNAVAILABLE)
+                            .body("SHUTTING DOWN".into())
+                            .unwrap();
                     }
-
-                    let Ok(a) = login.handle(&q, tmpls.clone()).await else {
+                    let Some(a) = login(&state, &mut q, &jar).await else {
                         return Response::builder()
                             .status(StatusCode::UNAUTHORIZED)
                             .body("UNAUTHORIZED".into())
                             .unwrap();
                     };
+                    let Some(logout_hook) = logout_hook(&state) else {
+                        return Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body("NO LOGOUT HOOK".into())
+                            .unwrap();
+                    };
 
-                    let logout = s.hooks.get("logout").unwrap()[0].clone();
                     let mut codec = codec_for_router.clone();
                     // Override codec from URL query parameter if provided
-                    tracing::info!("URL query params: {:?}", q);
                     if let Some(codec_str) = q.get("codec").and_then(|v| v.as_str()) {
                         tracing::info!("Found codec param: {}", codec_str);
                         if let Ok(ct) = codec_str.parse::<message::codec::CodecType>() {
@@ -105,24 +126,107 @@ async fn main() -> Result<()> {
                             tracing::info!("Codec set to: {:?}", ct);
                         }
                     }
-                    drop(s);
+                    // A subprotocol offered by the client wins over the query
+                    let ws = ws.protocols(Protocol::supported());
+                    let protocol = ws
+                        .selected_protocol()
+                        .and_then(|x| x.to_str().ok())
+                        .and_then(|x| x.parse::<Protocol>().ok())
+                        .unwrap_or_else(|| {
+                            let mut p = Protocol::new(codec.as_type());
+                            if let Some(v) = q.get("v").and_then(|v| v.as_str()) {
+                                p.version = v.parse().unwrap_or(p.version);
+                            }
+                            p
+                        });
+                    // Only a client that kept its store is sent patches
+                    // against what the session got before
+                    if q.get("base").and_then(|v| v.as_str()) != Some("1") {
+                        state.session.baselines().forget(&a.id);
+                    }
+                    let guard = state.track();
                     ws.on_upgrade(async move |socket| {
-                        handle_ws(socket, tx, state, config, tmpls.clone(), codec, &a).await;
-                        let _ = logout.handle::<Value>(&a.into(), tmpls.clone()).await;
+                        let _guard = guard;
+                        handle_ws(
+                            socket,
+                            tx,
+                            state.clone(),
+                            state.config.clone(),
+                            protocol,
+                            &a,
+                            addr,
+                        )
+                        .await;
+                        logout(&state, &logout_hook, a).await;
                     })
                 },
             ),
         )
-        .nest("/admin", admin_router())
-        .nest("/config", config_router())
+        .route(
+            "/events",
+            get(subscribe).post(publish).layer(Extension(outgo)),
+        )
         .nest("/debug", debug_router())
-        .fallback_service(ServeDir::new("./static"))
-        .with_state(shared);
+        .fallback_service(ServeDir::new("./static"));
+
+    // Served next to the admin routes, without a key so scrapers need no setup
+    let admin = Router::new()
+        .route("/metrics", get(metrics))
+        .nest(
+            "/admin",
+            admin_router().route_layer(from_fn_with_state(shared.clone(), require_admin)),
+        )
+        .nest(
+            "/config",
+            config_router().route_layer(from_fn_with_state(shared.clone(), require_config)),
+        );
+
+    let mut fds = ListenFd::from_env();
+    let server = config.load().server.clone();
+    let handle = Handle::new();
+    let admin_listen = config.load().admin.listen.clone();
+    if config.load().admin.keys.is_empty() {
+        warn!("no admin keys configured, /admin and /config are unauthenticated");
+    }
+    let (app, mut admin_serving) = if let Some(addr) = admin_listen {
+        let listener = bind(&mut fds, 1, &addr)?;
+        let admin = admin.with_state(shared.clone());
+        let serving = tokio::spawn(serve(listener, None, admin, handle.clone()));
+        (app.with_state(shared.clone()), Some(serving))
+    } else {
+        (app.merge(admin).with_state(shared.clone()), None)
+    };
+
+    let listener = bind(&mut fds, 0, &server.listen)?;
+    let mut serving = tokio::spawn(serve(listener, server.tls.clone(), app, handle.clone()));
 
-    let addr = "0.0.0.0:3000";
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    info!("Listening on {}", addr);
+    // Either listener failing takes the gateway down
+    tokio::select! {
+        r = &mut serving => return r?,
+        Some(r) = async {
+            match admin_serving.as_mut() {
+                Some(x) => Some(x.await),
+                None => None,
+            }
+        } => return r?,
+        _ = shutdown_signal() => {},
+    }
 
-    axum::serve(listener, app).await?;
+    info!("Shutting down");
+    shared.shutdown(server.grace()).await;
+    handle.graceful_shutdown(Some(server.grace()));
+    let _ = serving.await;
+    // The router owned the last outgo senders, the queue can drain now
+    if let Some(drained) = drained
+        && tokio::time::timeout(server.grace(), drained.wait())
+            .await
+            .is_err()
+    {
+        warn!("outgo queue not drained in time");
+    }
+    if let Some(provider) = provider {
+        // Flushing blocks on the exporter
+        let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+    }
     Okk(())
 }
@@ -0,0 +1,84 @@
+//! Round-trip and size comparison of the codecs on the sample payloads in
+//! `data/message`.
+//!
+//! cargo bench -p message --bench codec
+use message::codec::{ActiveCodec, CodecType};
+use serde_json::Value;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 2000;
+
+fn payloads() -> Vec<(String, Value)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../data/message");
+    let mut r = Vec::new();
+    for entry in std::fs::read_dir(&dir).expect("data/message") {
+        let path = entry.expect("entry").path();
+        if path.extension().is_none_or(|x| x != "yaml") {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path).expect("read payload");
+        match serde_yaml::from_str::<Value>(&content) {
+            Ok(v) => r.push((path.file_stem().unwrap().to_string_lossy().into_owned(), v)),
+            Err(e) => eprintln!("skip {}: {}", path.display(), e),
+        }
+    }
+    r.sort_by(|a, b| a.0.cmp(&b.0));
+    r
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let payloads = payloads();
+    let mut total = [(0usize, Duration::ZERO, Duration::ZERO); CodecType::ALL.len()];
+
+    println!(
+        "{:<24} {:>9} {:>8} {:>10} {:>10}",
+        "payload", "codec", "bytes", "encode", "decode"
+    );
+    for (name, value) in &payloads {
+        for (i, t) in CodecType::ALL.into_iter().enumerate() {
+            let codec = ActiveCodec::new(t);
+            let bytes = codec.encode(value).expect("encode");
+            let back: Value = codec.decode(&bytes).expect("decode");
+            assert_eq!(&back, value, "{} does not round-trip {}", t.name(), name);
+
+            let enc = time(|| {
+                black_box(codec.encode(black_box(value)).unwrap());
+            });
+            let dec = time(|| {
+                black_box(codec.decode::<Value>(black_box(&bytes)).unwrap());
+            });
+            println!(
+                "{:<24} {:>9} {:>8} {:>10.2?} {:>10.2?}",
+                name,
+                t.name(),
+                bytes.len(),
+                enc,
+                dec
+            );
+            total[i].0 += bytes.len();
+            total[i].1 += enc;
+            total[i].2 += dec;
+        }
+    }
+    println!();
+    for (t, (size, enc, dec)) in CodecType::ALL.into_iter().zip(total) {
+        println!(
+            "{:<24} {:>9} {:>8} {:>10.2?} {:>10.2?}",
+            "total",
+            t.name(),
+            size,
+            enc,
+            dec
+        );
+    }
+}
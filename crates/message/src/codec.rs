@@ -1,4 +1,5 @@
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Number, Value};
 use std::str::FromStr;
 use thiserror::Error;
 
@@ -18,11 +19,26 @@ pub enum CodecError {
 /// - serde 2.x incompatible (v1.x broken, v2.x API unstable)
 /// - No type self-description; Gateway cannot partially parse routing metadata
 /// - CBOR (`ciborium`) covers all advantages and adds cross-language support
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub enum CodecType {
     Json,
     #[default]
     Cbor,
+    MsgPack,
+    Postcard,
+}
+
+impl CodecType {
+    pub const ALL: [Self; 4] = [Self::Cbor, Self::MsgPack, Self::Postcard, Self::Json];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Cbor => "cbor",
+            Self::MsgPack => "msgpack",
+            Self::Postcard => "postcard",
+        }
+    }
 }
 
 impl FromStr for CodecType {
@@ -31,6 +47,8 @@ impl FromStr for CodecType {
         match s.to_lowercase().trim() {
             "json" => Ok(Self::Json),
             "cbor" => Ok(Self::Cbor),
+            "msgpack" | "messagepack" => Ok(Self::MsgPack),
+            "postcard" => Ok(Self::Postcard),
             _ => Err(CodecError::Unsupported(s.into())),
         }
     }
@@ -42,6 +60,8 @@ impl FromStr for CodecType {
 pub enum ActiveCodec {
     Json,
     Cbor,
+    MsgPack,
+    Postcard,
 }
 
 impl ActiveCodec {
@@ -49,6 +69,8 @@ impl ActiveCodec {
         match t {
             CodecType::Json => Self::Json,
             CodecType::Cbor => Self::Cbor,
+            CodecType::MsgPack => Self::MsgPack,
+            CodecType::Postcard => Self::Postcard,
         }
     }
 
@@ -57,6 +79,8 @@ impl ActiveCodec {
         match self {
             Self::Json => CodecType::Json,
             Self::Cbor => CodecType::Cbor,
+            Self::MsgPack => CodecType::MsgPack,
+            Self::Postcard => CodecType::Postcard,
         }
     }
 
@@ -69,6 +93,16 @@ impl ActiveCodec {
                     .map_err(|e| CodecError::Encode(e.to_string()))?;
                 Ok(out)
             }
+            // Named so structs become maps, the gateway reads fields by name
+            Self::MsgPack => {
+                rmp_serde::to_vec_named(value).map_err(|e| CodecError::Encode(e.to_string()))
+            }
+            Self::Postcard => {
+                let v =
+                    serde_json::to_value(value).map_err(|e| CodecError::Encode(e.to_string()))?;
+                postcard::to_allocvec(&Dynamic::from(v))
+                    .map_err(|e| CodecError::Encode(e.to_string()))
+            }
         }
     }
 
@@ -82,6 +116,195 @@ impl ActiveCodec {
                 ciborium::de::from_reader(&mut cursor)
                     .map_err(|e| CodecError::Decode(e.to_string()))
             }
+            Self::MsgPack => {
+                rmp_serde::from_slice(bytes).map_err(|e| CodecError::Decode(e.to_string()))
+            }
+            Self::Postcard => {
+                let v = postcard::from_bytes::<Dynamic>(bytes)
+                    .map_err(|e| CodecError::Decode(e.to_string()))?;
+                serde_json::from_value(v.into()).map_err(|e| CodecError::Decode(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Postcard is not self-describing, so it cannot decode into `Value` the way
+/// the gateway needs. Values go through this tagged mirror instead.
+#[derive(Serialize, Deserialize)]
+enum Dynamic {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Dynamic>),
+    Object(Vec<(String, Dynamic)>),
+}
+
+impl From<Value> for Dynamic {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Bool(b),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Self::Int(i)
+                } else if let Some(u) = n.as_u64() {
+                    Self::Uint(u)
+                } else {
+                    Self::Float(n.as_f64().unwrap_or_default())
+                }
+            }
+            Value::String(s) => Self::String(s),
+            Value::Array(a) => Self::Array(a.into_iter().map(Self::from).collect()),
+            Value::Object(o) => Self::Object(o.into_iter().map(|(k, v)| (k, v.into())).collect()),
+        }
+    }
+}
+
+impl From<Dynamic> for Value {
+    fn from(v: Dynamic) -> Self {
+        match v {
+            Dynamic::Null => Value::Null,
+            Dynamic::Bool(b) => Value::Bool(b),
+            Dynamic::Int(i) => Value::Number(i.into()),
+            Dynamic::Uint(u) => Value::Number(u.into()),
+            Dynamic::Float(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+            Dynamic::String(s) => Value::String(s),
+            Dynamic::Array(a) => Value::Array(a.into_iter().map(Value::from).collect()),
+            Dynamic::Object(o) => Value::Object(
+                o.into_iter()
+                    .map(|(k, v)| (k, v.into()))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+/// Version of the message shape, bumped on breaking changes so deployed UIs
+/// keep receiving the shape they were built for.
+///
+/// - v2: a `create` may arrive as a `patch` against the previous layout
+pub const PROTOCOL_VERSION: u16 = 2;
+
+const PROTOCOL_PREFIX: &str = "fluxora";
+
+/// WebSocket subprotocol `fluxora.<codec>[.deflate].v<version>`, e.g. `fluxora.cbor.v1`.
+///
+/// The WS stack has no permessage-deflate extension, compression is negotiated
+/// here instead: every frame is then binary and holds the DEFLATE-compressed
+/// encoding. Messages are compressed on their own, with no shared window, see
+/// `docs/decisions/003-app-level-deflate.md`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Protocol {
+    pub codec: CodecType,
+    pub deflate: bool,
+    pub version: u16,
+}
+
+impl Protocol {
+    pub fn new(codec: CodecType) -> Self {
+        Self {
+            codec,
+            deflate: false,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Every subprotocol the server accepts, most preferred first.
+    pub fn supported() -> Vec<String> {
+        let mut r = Vec::new();
+        for version in (1..=PROTOCOL_VERSION).rev() {
+            for codec in CodecType::ALL {
+                for deflate in [true, false] {
+                    r.push(
+                        Self {
+                            codec,
+                            deflate,
+                            version,
+                        }
+                        .to_string(),
+                    );
+                }
+            }
         }
+        r
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        let bytes = ActiveCodec::new(self.codec).encode(value)?;
+        if !self.deflate {
+            return Ok(bytes);
+        }
+        use flate2::{Compression, write::DeflateEncoder};
+        use std::io::Write;
+        let mut e = DeflateEncoder::new(Vec::new(), Compression::fast());
+        e.write_all(&bytes)
+            .and_then(|_| e.finish())
+            .map_err(|e| CodecError::Encode(e.to_string()))
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        let codec = ActiveCodec::new(self.codec);
+        if !self.deflate {
+            return codec.decode(bytes);
+        }
+        use flate2::read::DeflateDecoder;
+        use std::io::Read;
+        let mut out = Vec::new();
+        DeflateDecoder::new(bytes)
+            .read_to_end(&mut out)
+            .map_err(|e| CodecError::Decode(e.to_string()))?;
+        codec.decode(&out)
+    }
+
+    /// Whether frames are sent as binary rather than text.
+    pub fn is_binary(&self) -> bool {
+        self.deflate || self.codec != CodecType::Json
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", PROTOCOL_PREFIX, self.codec.name())?;
+        if self.deflate {
+            write!(f, ".deflate")?;
+        }
+        write!(f, ".v{}", self.version)
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = CodecError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unsupported = || CodecError::Unsupported(s.into());
+        let mut parts = s.trim().split('.');
+        if parts.next() != Some(PROTOCOL_PREFIX) {
+            return Err(unsupported());
+        }
+        let codec = parts.next().ok_or_else(unsupported)?.parse()?;
+        let mut next = parts.next().ok_or_else(unsupported)?;
+        let deflate = next == "deflate";
+        if deflate {
+            next = parts.next().ok_or_else(unsupported)?;
+        }
+        let version = next
+            .strip_prefix('v')
+            .and_then(|v| v.parse().ok())
+            .filter(|v| (1..=PROTOCOL_VERSION).contains(v))
+            .ok_or_else(unsupported)?;
+        if parts.next().is_some() {
+            return Err(unsupported());
+        }
+        Ok(Self {
+            codec,
+            deflate,
+            version,
+        })
     }
 }
+
+#[cfg(test)]
+#[path = "codec_test.rs"]
+mod tests;
@@ -0,0 +1,137 @@
+use super::*;
+use crate::ChatMessage;
+use crate::trace::TraceContext;
+use serde_json::json;
+
+/// What the WS upgrade does with `Protocol::supported()`: the first entry the
+/// client also offered wins.
+fn negotiate(offered: &[&str]) -> Option<Protocol> {
+    Protocol::supported()
+        .into_iter()
+        .find(|x| offered.contains(&x.as_str()))
+        .map(|x| x.parse().unwrap())
+}
+
+#[test]
+fn protocol_round_trip() {
+    for version in 1..=PROTOCOL_VERSION {
+        for codec in CodecType::ALL {
+            for deflate in [true, false] {
+                let p = Protocol {
+                    codec,
+                    deflate,
+                    version,
+                };
+                assert_eq!(p.to_string().parse::<Protocol>().unwrap(), p);
+            }
+        }
+    }
+    let p: Protocol = "fluxora.msgpack.deflate.v1".parse().unwrap();
+    assert_eq!(p.codec, CodecType::MsgPack);
+    assert!(p.deflate);
+    assert_eq!(p.version, 1);
+    assert_eq!(
+        Protocol::new(CodecType::Json).to_string(),
+        "fluxora.json.v2"
+    );
+}
+
+#[test]
+fn protocol_unknown_codec() {
+    assert!(matches!(
+        "fluxora.bincode.v1".parse::<Protocol>(),
+        Err(CodecError::Unsupported(x)) if x == "bincode"
+    ));
+    for s in ["fluxora..v1", "other.json.v1", "json.v1", ""] {
+        assert!(
+            matches!(s.parse::<Protocol>(), Err(CodecError::Unsupported(_))),
+            "{}",
+            s
+        );
+    }
+}
+
+#[test]
+fn protocol_bad_version() {
+    for s in [
+        "fluxora.json",
+        "fluxora.json.deflate",
+        "fluxora.json.v",
+        "fluxora.json.1",
+        "fluxora.json.vx",
+        "fluxora.json.v-1",
+        "fluxora.json.v0",
+        "fluxora.json.v99",
+        "fluxora.json.zip.v1",
+        "fluxora.json.v1.deflate",
+        "fluxora.json.v1.v1",
+    ] {
+        assert!(s.parse::<Protocol>().is_err(), "{}", s);
+    }
+}
+
+#[test]
+fn protocol_negotiation() {
+    let highest = PROTOCOL_VERSION;
+    let offered = [
+        "fluxora.cbor.v1".to_string(),
+        format!("fluxora.cbor.v{}", highest),
+        "fluxora.json.v1".to_string(),
+    ];
+    let offered: Vec<&str> = offered.iter().map(String::as_str).collect();
+    assert_eq!(
+        negotiate(&offered),
+        Some(Protocol {
+            codec: CodecType::Cbor,
+            deflate: false,
+            version: highest,
+        })
+    );
+    // The version wins over the codec preference
+    assert_eq!(
+        negotiate(&["fluxora.cbor.v1", "fluxora.json.v2"]),
+        Some(Protocol::new(CodecType::Json))
+    );
+    // Deflate is preferred within a version
+    assert!(
+        negotiate(&["fluxora.postcard.v1", "fluxora.postcard.deflate.v1"])
+            .unwrap()
+            .deflate
+    );
+    assert_eq!(negotiate(&["fluxora.json.v0", "fluxora.json.v3"]), None);
+    assert_eq!(negotiate(&[]), None);
+}
+
+#[test]
+fn postcard_chat_message() {
+    let m = ChatMessage::<u64> {
+        sender: "s1".into(),
+        created: Some(1_700_000_000_000),
+        content: json!({
+            "create": {
+                "type": "case",
+                "data": {
+                    "n": [1, -2, 3.5, u64::MAX, null],
+                    "ok": true,
+                    "text": "ünïcode",
+                    "nested": {"empty": {}, "list": [[], [{"a": "b"}]]}
+                }
+            }
+        }),
+        trace: Some(TraceContext {
+            traceparent: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".into(),
+            tracestate: None,
+        }),
+        version: Some(PROTOCOL_VERSION),
+        channel: Some("#general".into()),
+    };
+    let codec = ActiveCodec::new(CodecType::Postcard);
+    let back: ChatMessage<u64> = codec.decode(&codec.encode(&m).unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&back).unwrap(),
+        serde_json::to_value(&m).unwrap()
+    );
+    // Through the gateway's view of it as well
+    let v: Value = codec.decode(&codec.encode(&m).unwrap()).unwrap();
+    assert_eq!(v, serde_json::to_value(&m).unwrap());
+}
@@ -2,7 +2,7 @@ use crate::config;
 use crate::time::Created;
 use crate::{
     Event,
-    queue::{MessageQueueIncome, MessageQueueOutgo},
+    queue::{Drained, MessageQueueIncome, MessageQueueOutgo},
 };
 use anyhow::{Ok as Okk, Result, anyhow};
 use config::{IggyIncomeConfig, IggyOutgoConfig};
@@ -19,9 +19,10 @@ use iggy::prelude::{
 use tokio::sync::{
     Mutex,
     mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    watch,
 };
 use tokio::task::spawn;
-use tracing::{error, info, warn};
+use tracing::{Instrument, error, info_span};
 
 #[derive(Clone, Debug)]
 pub struct IggyManagerOutgo<T>
@@ -29,6 +30,7 @@ where
     T: Send + Serialize + for<'de> Deserialize<'de>,
 {
     tx: Option<UnboundedSender<T>>,
+    drained: Option<Drained>,
     producer: IggyOutgoConfig,
 }
 
@@ -37,18 +39,23 @@ where
     T: Send + Serialize + for<'de> Deserialize<'de> + 'static,
 {
     pub fn new(producer: IggyOutgoConfig) -> Self {
-        Self { tx: None, producer }
+        Self {
+            tx: None,
+            drained: None,
+            producer,
+        }
     }
 }
 
 impl<T> MessageQueueOutgo for IggyManagerOutgo<T>
 where
-    T: Debug + Clone + Send + Serialize + for<'de> Deserialize<'de> + 'static,
+    T: Debug + Clone + Send + Serialize + for<'de> Deserialize<'de> + Event<Created> + 'static,
 {
     type Item = T;
 
     async fn run(&mut self) -> Result<()> {
         let (tx, mut producer_rx) = unbounded_channel::<Self::Item>();
+        let (done_tx, done_rx) = watch::channel(false);
         let cfg = &self.producer;
         let client = IggyClient::from_connection_string(&cfg.to_conn())?;
 
@@ -71,21 +78,36 @@ where
             }
         };
 
+        let topic = cfg.topic.clone();
         spawn(async move {
             // let topic : Vec<&str> = producer_cfg.topic.iter().map(<_>::as_ref).collect();
             while let Some(value) = producer_rx.recv().await {
+                // The trace context travels inside the JSON payload
+                let span = info_span!("queue.send", topic = %topic, event = value.event());
+                if let Some(t) = value.trace() {
+                    t.attach(&span);
+                }
                 let value = serde_json::to_string(&value).expect("serde to string");
-                let _ = producer.send(vec![IggyMessage::from(value)]).await;
+                let _ = producer
+                    .send(vec![IggyMessage::from(value)])
+                    .instrument(span)
+                    .await;
             }
+            let _ = done_tx.send(true);
         });
 
         self.tx = Some(tx);
+        self.drained = Some(Drained(done_rx));
         Ok(())
     }
 
     fn get_tx(&self) -> Option<UnboundedSender<Self::Item>> {
         self.tx.clone()
     }
+
+    fn get_drained(&self) -> Option<Drained> {
+        self.drained.clone()
+    }
 }
 
 #[derive(Clone, Debug)]
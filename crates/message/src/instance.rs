@@ -3,7 +3,7 @@ use crate::config::{Queue, QueueIncome, QueueOutgo};
 use crate::iggy::{IggyManagerIncome, IggyManagerOutgo};
 #[cfg(feature = "kafka")]
 use crate::kafka::{KafkaManagerIncome, KafkaManagerOutgo};
-use crate::queue::{MessageQueue, MessageQueueIncome, MessageQueueOutgo};
+use crate::queue::{Drained, MessageQueue, MessageQueueIncome, MessageQueueOutgo};
 use crate::{Event, time::Created};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -21,6 +21,7 @@ impl MessageQueue for Queue {
     ) -> (
         Option<UnboundedSender<O>>,
         Option<Arc<Mutex<UnboundedReceiver<I>>>>,
+        Option<Drained>,
     )
     where
         I: Event<Created> + Send + Serialize + for<'a> Deserialize<'a> + Clone + Debug + 'static,
@@ -41,21 +42,21 @@ impl MessageQueue for Queue {
             }
         };
 
-        let outgo_tx = match self.outgo {
+        let (outgo_tx, drained) = match self.outgo {
             #[cfg(feature = "kafka")]
             QueueOutgo::kafka(outgo) => {
                 let mut outgo_mq: KafkaManagerOutgo<O> = KafkaManagerOutgo::new(outgo);
                 let _ = outgo_mq.run().await;
-                outgo_mq.get_tx()
+                (outgo_mq.get_tx(), outgo_mq.get_drained())
             }
             #[cfg(feature = "iggy")]
             QueueOutgo::iggy(outgo) => {
                 let mut outgo_mq: IggyManagerOutgo<O> = IggyManagerOutgo::new(outgo);
                 let _ = outgo_mq.run().await;
-                outgo_mq.get_tx()
+                (outgo_mq.get_tx(), outgo_mq.get_drained())
             }
         };
 
-        (outgo_tx, income_rx)
+        (outgo_tx, income_rx, drained)
     }
 }
@@ -1,8 +1,9 @@
 use crate::config;
 use crate::time::Created;
+use crate::trace::{TRACEPARENT, TRACESTATE, TraceContext};
 use crate::{
     Event,
-    queue::{MessageQueueIncome, MessageQueueOutgo},
+    queue::{Drained, MessageQueueIncome, MessageQueueOutgo},
 };
 use anyhow::Result;
 use ciborium::ser::into_writer;
@@ -12,8 +13,8 @@ use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance};
 use rdkafka::error::KafkaResult;
-use rdkafka::message::{Header, Message, OwnedHeaders};
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use rdkafka::topic_partition_list::TopicPartitionList;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -23,9 +24,10 @@ use std::time::Duration;
 use tokio::sync::{
     Mutex,
     mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    watch,
 };
 use tokio::task::spawn;
-use tracing::{error, info, warn};
+use tracing::{Instrument, error, info, info_span, warn};
 
 #[derive(Clone)]
 pub struct KafkaManagerOutgo<T>
@@ -33,6 +35,7 @@ where
     T: Send + Serialize + for<'de> Deserialize<'de>,
 {
     tx: Option<UnboundedSender<T>>,
+    drained: Option<Drained>,
     producer: KafkaOutgoConfig,
 }
 
@@ -41,18 +44,23 @@ where
     T: Send + Serialize + for<'de> Deserialize<'de> + 'static,
 {
     pub fn new(producer: KafkaOutgoConfig) -> Self {
-        Self { tx: None, producer }
+        Self {
+            tx: None,
+            drained: None,
+            producer,
+        }
     }
 }
 
 impl<T> MessageQueueOutgo for KafkaManagerOutgo<T>
 where
-    T: Debug + Clone + Send + Serialize + for<'de> Deserialize<'de> + 'static,
+    T: Debug + Clone + Send + Serialize + for<'de> Deserialize<'de> + Event<Created> + 'static,
 {
     type Item = T;
 
     async fn run(&mut self) -> Result<()> {
         let (tx, mut rx) = unbounded_channel::<Self::Item>();
+        let (done_tx, done_rx) = watch::channel(false);
         let cfg = self.producer.clone();
 
         let producer: FutureProducer = ClientConfig::new()
@@ -69,27 +77,55 @@ where
                     error!("CBOR encode failed: {}", e);
                     continue;
                 }
-                let _delivery_status = producer
+                let mut headers = OwnedHeaders::new();
+                if let Some(t) = value.trace() {
+                    headers = headers.insert(Header {
+                        key: TRACEPARENT,
+                        value: Some(&t.traceparent),
+                    });
+                    if let Some(state) = &t.tracestate {
+                        headers = headers.insert(Header {
+                            key: TRACESTATE,
+                            value: Some(state),
+                        });
+                    }
+                }
+                let span = info_span!("queue.send", topic = %cfg.topic, event = value.event());
+                if let Some(t) = value.trace() {
+                    t.attach(&span);
+                }
+                let delivery = producer
                     .send(
-                        FutureRecord::to(&cfg.topic).payload(&buf).key("").headers(
-                            OwnedHeaders::new().insert(Header {
-                                key: "",
-                                value: Some(""),
-                            }),
-                        ),
+                        FutureRecord::to(&cfg.topic)
+                            .payload(&buf)
+                            .key("")
+                            .headers(headers),
                         Duration::from_secs(0),
                     )
+                    .instrument(span)
                     .await;
+                if let Err((e, _)) = delivery {
+                    error!("Kafka delivery failed: {}", e);
+                }
             }
+            // All senders are gone, make sure nothing is left in librdkafka's buffer
+            let _ =
+                tokio::task::spawn_blocking(move || producer.flush(Duration::from_secs(5))).await;
+            let _ = done_tx.send(true);
         });
 
         self.tx = Some(tx);
+        self.drained = Some(Drained(done_rx));
         Ok(())
     }
 
     fn get_tx(&self) -> Option<UnboundedSender<Self::Item>> {
         self.tx.clone()
     }
+
+    fn get_drained(&self) -> Option<Drained> {
+        self.drained.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -157,6 +193,11 @@ where
                         match ciborium::de::from_reader::<Self::Item, _>(&mut cursor) {
                             Ok(mut value) => {
                                 value.set_time(m.timestamp().into());
+                                if value.trace().is_none()
+                                    && let Some(t) = header_trace(&m)
+                                {
+                                    value.set_trace(t);
+                                }
                                 if let Err(e) = tx.send(value) {
                                     error!("Failed to send message from consumer: {}", e);
                                 }
@@ -188,6 +229,23 @@ where
     }
 }
 
+/// Trace context from the record headers, for producers that do not embed it in the payload.
+fn header_trace<M: Message>(m: &M) -> Option<TraceContext> {
+    let headers = m.headers()?;
+    let get = |k: &str| {
+        headers
+            .iter()
+            .find(|h| h.key == k)
+            .and_then(|h| h.value)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(|v| v.to_owned())
+    };
+    Some(TraceContext {
+        traceparent: get(TRACEPARENT)?,
+        tracestate: get(TRACESTATE),
+    })
+}
+
 struct CustomContext;
 
 impl ClientContext for CustomContext {}
@@ -14,11 +14,33 @@ pub mod instance;
 mod kafka;
 #[cfg(any(feature = "kafka", feature = "iggy"))]
 pub mod queue;
+pub mod record;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod time;
+pub mod trace;
+use trace::TraceContext;
+
+/// Matches event names and the like, `*` and `?` wildcards.
+pub fn glob(p: &[u8], s: &[u8]) -> bool {
+    match (p.first(), s.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob(&p[1..], s) || (!s.is_empty() && glob(p, &s[1..])),
+        (Some(b'?'), Some(_)) => glob(&p[1..], &s[1..]),
+        (Some(a), Some(b)) if a == b => glob(&p[1..], &s[1..]),
+        _ => false,
+    }
+}
 
 pub trait Event<C> {
     fn event(&self) -> Option<&str>;
     fn set_time(&mut self, time: C);
+    fn trace(&self) -> Option<&TraceContext> {
+        None
+    }
+    fn set_trace(&mut self, _trace: TraceContext) {}
+    /// Protocol version of the client the message comes from.
+    fn set_version(&mut self, _version: u16) {}
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -35,6 +57,15 @@ impl<C> Event<C> for Envelope<C> {
     fn set_time(&mut self, time: C) {
         self.message.set_time(time);
     }
+    fn trace(&self) -> Option<&TraceContext> {
+        self.message.trace()
+    }
+    fn set_trace(&mut self, trace: TraceContext) {
+        self.message.set_trace(trace);
+    }
+    fn set_version(&mut self, version: u16) {
+        self.message.set_version(version);
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -42,6 +73,15 @@ pub struct ChatMessage<C> {
     pub sender: Session,
     pub created: Option<C>,
     pub content: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    /// See `codec::PROTOCOL_VERSION`, set by the gateway on inbound messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u16>,
+    /// Channel name or id chosen by the client, the session's active channel
+    /// when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
 }
 
 impl<C> From<(Session, Value)> for ChatMessage<C>
@@ -49,10 +89,19 @@ where
     C: Default,
 {
     fn from(value: (Session, Value)) -> Self {
+        let channel = match value.1.get("channel") {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        };
         ChatMessage {
             sender: value.0,
             created: Some(C::default()),
             content: value.1,
+            // Inherit the span the message is built in
+            trace: TraceContext::current(),
+            version: None,
+            channel,
         }
     }
 }
@@ -78,4 +127,16 @@ impl<C> Event<C> for ChatMessage<C> {
     fn set_time(&mut self, time: C) {
         self.created = Some(time);
     }
+
+    fn trace(&self) -> Option<&TraceContext> {
+        self.trace.as_ref()
+    }
+
+    fn set_trace(&mut self, trace: TraceContext) {
+        self.trace = Some(trace);
+    }
+
+    fn set_version(&mut self, version: u16) {
+        self.version = Some(version);
+    }
 }
@@ -6,8 +6,20 @@ use std::sync::Arc;
 use tokio::sync::{
     Mutex,
     mpsc::{UnboundedReceiver, UnboundedSender},
+    watch,
 };
 
+/// Resolves once the outgo task has published everything sent
+/// before the last `UnboundedSender` was dropped.
+#[derive(Debug, Clone)]
+pub struct Drained(pub(crate) watch::Receiver<bool>);
+
+impl Drained {
+    pub async fn wait(mut self) {
+        let _ = self.0.wait_for(|x| *x).await;
+    }
+}
+
 pub trait MessageQueueOutgo {
     type Item: Debug + Send + Serialize + for<'a> Deserialize<'a>;
 
@@ -16,6 +28,9 @@ pub trait MessageQueueOutgo {
 
     #[allow(unused)]
     fn get_tx(&self) -> Option<UnboundedSender<Self::Item>>;
+
+    #[allow(unused)]
+    fn get_drained(&self) -> Option<Drained>;
 }
 
 pub trait MessageQueueIncome {
@@ -35,6 +50,7 @@ pub trait MessageQueue {
     ) -> (
         Option<UnboundedSender<O>>,
         Option<Arc<Mutex<UnboundedReceiver<I>>>>,
+        Option<Drained>,
     )
     where
         I: Event<Created> + Send + Serialize + for<'a> Deserialize<'a> + Clone + Debug + 'static,
@@ -0,0 +1,162 @@
+//! Session recordings: one JSON [`Entry`] per line, as written by the gateway
+//! `[record]` option, and the comparison used to replay them.
+use crate::session::Session;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "in")]
+    Inbound,
+    #[serde(rename = "out")]
+    Outbound,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub direction: Direction,
+    pub session: Session,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    pub message: Value,
+}
+
+impl Entry {
+    /// Stamps `message` with the current time, the event is read from a
+    /// `ChatMessage` or a bare client message.
+    pub fn new(direction: Direction, session: Session, message: Value) -> Self {
+        let event = message
+            .pointer("/content/event")
+            .or_else(|| message.get("event"))
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_owned());
+        Self {
+            time: Utc::now(),
+            direction,
+            session,
+            event,
+            message,
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("recording {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            serde_json::from_str(l).with_context(|| format!("{}:{}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// The messages of `direction` in the recording, in order. Inbound ones are
+/// the `ChatMessage` a service got, outbound ones what the client got.
+pub fn messages(entries: &[Entry], direction: Direction) -> Vec<Value> {
+    entries
+        .iter()
+        .filter(|e| e.direction == direction)
+        .map(|e| e.message.clone())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mismatch {
+    /// Position in the outbound sequence.
+    pub index: usize,
+    /// JSON pointer of the differing value.
+    pub path: String,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |v: &Option<Value>| v.as_ref().map_or("<missing>".into(), |v| v.to_string());
+        write!(
+            f,
+            "#{} {}: expected {}, got {}",
+            self.index,
+            if self.path.is_empty() {
+                "/"
+            } else {
+                &self.path
+            },
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+/// JSON pointers of a `ChatMessage` that differ on every run, the sender
+/// being a session id given at login.
+pub const VOLATILE: [&str; 3] = ["/created", "/trace", "/sender"];
+
+/// Compares outbound messages pairwise, skipping the JSON pointers in `ignore`
+/// (timestamps, generated ids).
+pub fn diff(expected: &[Value], actual: &[Value], ignore: &[String]) -> Vec<Mismatch> {
+    let mut r = Vec::new();
+    for index in 0..expected.len().max(actual.len()) {
+        walk(
+            index,
+            String::new(),
+            expected.get(index),
+            actual.get(index),
+            ignore,
+            &mut r,
+        );
+    }
+    r
+}
+
+fn walk(
+    index: usize,
+    path: String,
+    expected: Option<&Value>,
+    actual: Option<&Value>,
+    ignore: &[String],
+    r: &mut Vec<Mismatch>,
+) {
+    if ignore.contains(&path) {
+        return;
+    }
+    match (expected, actual) {
+        (Some(Value::Object(e)), Some(Value::Object(a))) => {
+            for k in e.keys().chain(a.keys().filter(|k| !e.contains_key(*k))) {
+                let p = format!("{}/{}", path, k.replace('~', "~0").replace('/', "~1"));
+                walk(index, p, e.get(k), a.get(k), ignore, r);
+            }
+        }
+        (Some(Value::Array(e)), Some(Value::Array(a))) => {
+            for i in 0..e.len().max(a.len()) {
+                walk(
+                    index,
+                    format!("{}/{}", path, i),
+                    e.get(i),
+                    a.get(i),
+                    ignore,
+                    r,
+                );
+            }
+        }
+        (e, a) if e != a => r.push(Mismatch {
+            index,
+            path,
+            expected: e.cloned(),
+            actual: a.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+#[path = "record_test.rs"]
+mod tests;
@@ -0,0 +1,91 @@
+use super::*;
+use serde_json::json;
+
+fn ignore(paths: &[&str]) -> Vec<String> {
+    paths.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn same_messages() {
+    let v = vec![json!({"a": [1, {"b": null}]}), json!("x")];
+    assert!(diff(&v, &v, &[]).is_empty());
+}
+
+#[test]
+fn leaf_paths() {
+    let expected = [json!({"content": {"data": [1, 2], "a/b": "x", "t~": 1}})];
+    let actual = [json!({"content": {"data": [1, 3], "a/b": "y", "t~": 1, "new": true}})];
+    let r = diff(&expected, &actual, &[]);
+    let paths: Vec<&str> = r.iter().map(|x| x.path.as_str()).collect();
+    // Keys in map order
+    assert_eq!(paths, ["/content/a~1b", "/content/data/1", "/content/new"]);
+    assert_eq!(r[1].expected, Some(json!(2)));
+    assert_eq!(r[1].actual, Some(json!(3)));
+    assert_eq!(r[2].expected, None);
+    assert_eq!(
+        r[2].to_string(),
+        "#0 /content/new: expected <missing>, got true"
+    );
+}
+
+#[test]
+fn arrays_and_messages_of_other_lengths() {
+    let r = diff(&[json!([1, 2])], &[json!([1]), json!({})], &[]);
+    assert_eq!(r.len(), 2);
+    assert_eq!((r[0].index, r[0].path.as_str()), (0, "/1"));
+    assert_eq!(r[0].actual, None);
+    // A whole message missing on one side
+    assert_eq!((r[1].index, r[1].path.as_str()), (1, ""));
+    assert_eq!(r[1].expected, None);
+    assert_eq!(r[1].to_string(), "#1 /: expected <missing>, got {}");
+}
+
+#[test]
+fn ignored_pointers() {
+    let expected = [json!({"created": 1, "content": {"id": "a", "v": 1}})];
+    let actual = [json!({"created": 2, "content": {"id": "b", "v": 1}})];
+    assert_eq!(diff(&expected, &actual, &[]).len(), 2);
+    assert!(diff(&expected, &actual, &ignore(&["/created", "/content/id"])).is_empty());
+    // The whole subtree
+    assert!(diff(&expected, &actual, &ignore(&["/created", "/content"])).is_empty());
+}
+
+#[test]
+fn entry_event() {
+    let chat = json!({"sender": "s", "content": {"event": "chat", "data": 1}});
+    let e = Entry::new(Direction::Outbound, "s".into(), chat);
+    assert_eq!(e.event.as_deref(), Some("chat"));
+    let bare = json!({"event": "message", "data": 1});
+    let e = Entry::new(Direction::Inbound, "s".into(), bare);
+    assert_eq!(e.event.as_deref(), Some("message"));
+    assert_eq!(serde_json::to_value(&e).unwrap()["direction"], json!("in"));
+}
+
+#[test]
+fn load_lines() {
+    let dir = std::env::temp_dir().join(format!("record-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let entry = Entry::new(Direction::Inbound, "s".into(), json!({"event": "x"}));
+    let line = serde_json::to_string(&entry).unwrap();
+
+    let good = dir.join("good.jsonl");
+    std::fs::write(&good, format!("{line}\n\n{line}\n")).unwrap();
+    assert_eq!(load(&good).unwrap().len(), 2);
+
+    let bad = dir.join("bad.jsonl");
+    std::fs::write(&bad, format!("{line}\nnot json\n")).unwrap();
+    let e = load(&bad).unwrap_err();
+    assert!(e.to_string().ends_with("bad.jsonl:2"), "{e}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn messages_by_direction() {
+    let entries = [
+        Entry::new(Direction::Inbound, "s".into(), json!(1)),
+        Entry::new(Direction::Outbound, "s".into(), json!(2)),
+        Entry::new(Direction::Inbound, "s".into(), json!(3)),
+    ];
+    assert_eq!(messages(&entries, Direction::Inbound), [json!(1), json!(3)]);
+    assert_eq!(messages(&entries, Direction::Outbound), [json!(2)]);
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use axum::Router;
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use listenfd::ListenFd;
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+use tracing::info;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tls {
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Server {
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    pub tls: Option<Tls>,
+    /// Seconds to wait for in-flight work and queues to drain on shutdown.
+    #[serde(default = "default_grace")]
+    pub grace: u64,
+}
+
+fn default_listen() -> String {
+    "0.0.0.0:3000".to_owned()
+}
+
+fn default_grace() -> u64 {
+    10
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            listen: default_listen(),
+            tls: None,
+            grace: default_grace(),
+        }
+    }
+}
+
+impl Server {
+    /// Defaults, listening on `addr`.
+    pub fn on(addr: &str) -> Self {
+        Self {
+            listen: addr.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace)
+    }
+}
+
+/// Takes the socket passed by systemd/systemfd at `idx` if any, else binds `addr`.
+pub fn bind(fds: &mut ListenFd, idx: usize, addr: &str) -> Result<TcpListener> {
+    let listener = match fds.take_tcp_listener(idx)? {
+        Some(l) => {
+            info!("Using activated socket {} ({:?})", idx, l.local_addr());
+            l
+        }
+        None => {
+            info!("Listening on {}", addr);
+            TcpListener::bind(addr)?
+        }
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    tls: Option<Tls>,
+    app: Router,
+    handle: Handle<SocketAddr>,
+) -> Result<()> {
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+            axum_server::from_tcp_rustls(listener, config)?
+                .handle(handle)
+                .serve(app)
+                .await?
+        }
+        None => {
+            axum_server::from_tcp(listener)?
+                .handle(handle)
+                .serve(app)
+                .await?
+        }
+    }
+    Ok(())
+}
+
+/// Resolves on ctrl-c or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let term = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        if let Ok(mut s) = signal(SignalKind::terminate()) {
+            s.recv().await;
+        }
+    };
+    #[cfg(not(unix))]
+    let term = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = term => {},
+    }
+}
@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// W3C trace-context carried by a message across the queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TraceContext {
+    pub traceparent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+}
+
+/// `[trace.otlp]` section, spans are exported over OTLP/HTTP when present.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Otlp {
+    #[serde(default = "default_endpoint")]
+    pub endpoint: String,
+    /// `service.name` resource, defaults to the binary name.
+    pub service: Option<String>,
+}
+
+fn default_endpoint() -> String {
+    "http://localhost:4318/v1/traces".to_owned()
+}
+
+impl TraceContext {
+    pub fn new(traceparent: impl Into<String>) -> Self {
+        Self {
+            traceparent: traceparent.into(),
+            tracestate: None,
+        }
+    }
+
+    /// Context of the current span, `None` when it is not sampled by OpenTelemetry.
+    #[cfg(feature = "otel")]
+    pub fn current() -> Option<Self> {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry::trace::TraceContextExt;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = tracing::Span::current().context();
+        if !cx.span().span_context().is_valid() {
+            return None;
+        }
+        let mut carrier = std::collections::HashMap::new();
+        TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+        Some(Self {
+            traceparent: carrier.remove(TRACEPARENT)?,
+            tracestate: carrier.remove(TRACESTATE).filter(|x| !x.is_empty()),
+        })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn current() -> Option<Self> {
+        None
+    }
+
+    /// Makes `span` a child of this context.
+    #[cfg(feature = "otel")]
+    pub fn attach(&self, span: &tracing::Span) {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let mut carrier = std::collections::HashMap::new();
+        carrier.insert(TRACEPARENT.to_owned(), self.traceparent.clone());
+        if let Some(x) = &self.tracestate {
+            carrier.insert(TRACESTATE.to_owned(), x.clone());
+        }
+        let cx = TraceContextPropagator::new().extract(&carrier);
+        if let Err(e) = span.set_parent(cx) {
+            tracing::debug!("set trace parent: {:?}", e);
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn attach(&self, _span: &tracing::Span) {}
+}
+
+#[cfg(feature = "otel")]
+pub use otlp::layer;
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use super::Otlp;
+    use anyhow::Result;
+    use opentelemetry::{global, trace::TracerProvider};
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{
+        Resource,
+        propagation::TraceContextPropagator,
+        trace::{SdkTracer, SdkTracerProvider},
+    };
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Builds the tracing layer exporting to `cfg.endpoint`. Keep the provider
+    /// and call `shutdown` on exit so buffered spans are flushed.
+    pub fn layer<S>(
+        cfg: &Otlp,
+        service: &str,
+    ) -> Result<(OpenTelemetryLayer<S, SdkTracer>, SdkTracerProvider)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&cfg.endpoint)
+            .build()?;
+        let service = cfg.service.clone().unwrap_or_else(|| service.to_owned());
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(service.clone())
+                    .build(),
+            )
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider.clone());
+        let tracer = provider.tracer(service);
+        Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+#[path = "trace_test.rs"]
+mod tests;
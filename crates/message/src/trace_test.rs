@@ -0,0 +1,64 @@
+use super::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, registry};
+
+/// Answers one OTLP/HTTP request, handing over its request line and body.
+fn collector() -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((k, v)) = header.split_once(':')
+                && k.eq_ignore_ascii_case("content-length")
+            {
+                length = v.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .unwrap();
+        let _ = tx.send((line, body));
+    });
+    (endpoint, rx)
+}
+
+#[test]
+fn export_span() {
+    let (endpoint, rx) = collector();
+    let cfg = Otlp {
+        endpoint,
+        service: Some("trace-test".into()),
+    };
+    let (layer, provider) = layer(&cfg, "message").unwrap();
+    let subscriber = registry().with(layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("exported-span");
+        let _entered = span.enter();
+        assert!(TraceContext::current().is_some());
+    });
+    provider.shutdown().unwrap();
+
+    let (line, body) = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(line.starts_with("POST /v1/traces "), "{line}");
+    // Protobuf keeps strings as they are
+    let find = |s: &[u8]| body.windows(s.len()).any(|x| x == s);
+    assert!(find(b"exported-span"));
+    assert!(find(b"trace-test"));
+}
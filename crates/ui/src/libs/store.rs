@@ -1,17 +1,19 @@
 use super::ws::{WebSocketHandle, use_web_socket};
-use anyhow::Result;
+use anyhow::{Result, bail};
 use brick::{
     Brick, BrickOps,
     merge::{BrickOp, Concat, Delete, Replace},
 };
-use content::{Content, Message, Method, Outflow};
+use content::{Content, Influx, Message, Method, Outflow};
 #[allow(unused_imports)]
 use dioxus::prelude::*;
 use js_sys::wasm_bindgen::JsError;
-use message::codec::ActiveCodec;
+use message::codec::{ActiveCodec, Protocol};
 use minijinja::Environment;
 use serde_json::Value;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::str;
 use std::sync::{LazyLock, RwLock};
 
@@ -21,6 +23,16 @@ static TMPL: LazyLock<RwLock<Environment>> = LazyLock::new(|| {
     RwLock::new(env)
 });
 
+/// A fresh sampled W3C `traceparent`, so a trace starts at the user action.
+fn traceparent() -> String {
+    let hex = |n: usize| -> String {
+        (0..n)
+            .map(|_| format!("{:x}", (js_sys::Math::random() * 16.0) as u8))
+            .collect()
+    };
+    format!("00-{}-{}-01", hex(32), hex(16))
+}
+
 #[derive(Clone)]
 pub struct Status {
     pub ws: WebSocketHandle,
@@ -36,15 +48,25 @@ impl Status {
             event: event.as_ref().to_string(),
             id,
             data: content,
+            trace: Some(traceparent()),
+            channel: None,
         };
 
-        if let Ok(buf) = self.codec.encode(&x) {
-            let msg = match &self.codec {
+        // The event stream is JSON whatever the codec
+        let codec = if self.ws.is_stream() {
+            ActiveCodec::Json
+        } else {
+            self.codec.clone()
+        };
+        if let Ok(buf) = codec.encode(&x) {
+            let msg = match &codec {
                 ActiveCodec::Json => {
                     let s = String::from_utf8(buf).unwrap_or_default();
                     gloo_net::websocket::Message::Text(s)
                 }
-                ActiveCodec::Cbor => gloo_net::websocket::Message::Bytes(buf),
+                ActiveCodec::Cbor | ActiveCodec::MsgPack | ActiveCodec::Postcard => {
+                    gloo_net::websocket::Message::Bytes(buf)
+                }
             };
             let _ = self.ws.send(msg).await;
         }
@@ -115,13 +137,61 @@ fn dispatch(
                     list.write().entry(e).or_default().push(d.clone());
                 }
             }
+            Content::Error(e) => {
+                dioxus::logger::tracing::warn!("message rejected: {}: {}", e.code, e.message)
+            }
+            // Resolved into a `Create` on receipt
+            Content::Patch(_) => {}
             Content::Empty => {}
         }
     }
 }
 
+fn brick(x: Influx<Value>) -> Result<Influx<Brick>> {
+    Ok(Influx {
+        event: x.event,
+        data: serde_json::from_value(x.data)?,
+        method: x.method,
+        channel: x.channel,
+    })
+}
+
+/// Turns layout patches into full layouts, `base` is the last layout as
+/// received, before rendering, which is what the gateway diffs against.
+fn resolve(msg: Message<Value>, base: &mut Option<Value>) -> Result<Message<Brick>> {
+    let mut content = Vec::with_capacity(msg.content.len());
+    for c in msg.content {
+        content.push(match c {
+            Content::Create(x) => {
+                *base = Some(x.data.clone());
+                Content::Create(brick(x)?)
+            }
+            Content::Patch(mut x) => {
+                let Some(mut layout) = base.clone() else {
+                    bail!("patch for {} without a layout", x.event);
+                };
+                let ops: json_patch::Patch = serde_json::from_value(x.data)?;
+                json_patch::patch(&mut layout, &ops)?;
+                *base = Some(layout.clone());
+                x.data = layout;
+                Content::Create(brick(x)?)
+            }
+            Content::Set(x) => Content::Set(brick(x)?),
+            Content::Join(x) => Content::Join(brick(x)?),
+            Content::Tmpl(x) => Content::Tmpl(x),
+            Content::Error(x) => Content::Error(x),
+            Content::Empty => Content::Empty,
+        });
+    }
+    Ok(Message {
+        sender: msg.sender,
+        created: msg.created,
+        content,
+    })
+}
+
 pub fn use_status(url: &str, codec: ActiveCodec) -> Result<Status, JsError> {
-    let ws = use_web_socket(url)?;
+    let ws = use_web_socket(url, &[Protocol::new(codec.as_type()).to_string()])?;
     let bytes_signal = ws.message_bytes();
     let recv_codec = codec.clone();
 
@@ -132,11 +202,21 @@ pub fn use_status(url: &str, codec: ActiveCodec) -> Result<Status, JsError> {
     });
     let mut data = use_signal::<HashMap<String, Brick>>(HashMap::new);
     let mut list = use_signal::<HashMap<String, Vec<Brick>>>(HashMap::new);
+    let base = use_hook(|| Rc::new(RefCell::new(None::<Value>)));
 
     use_memo(move || {
         let b = &bytes_signal();
         if !b.is_empty() {
-            match recv_codec.decode::<Message<Brick>>(b) {
+            let codec = if ws.is_stream() {
+                &ActiveCodec::Json
+            } else {
+                &recv_codec
+            };
+            let act = codec
+                .decode::<Message<Value>>(b)
+                .map_err(anyhow::Error::from)
+                .and_then(|x| resolve(x, &mut base.borrow_mut()));
+            match act {
                 Ok(act) => dispatch(act, &mut layout, &mut data, &mut list),
                 Err(err) => {
                     if let Ok(act) = &String::from_utf8(b.clone()) {
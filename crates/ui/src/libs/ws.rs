@@ -1,42 +1,135 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use anyhow::{Result, bail};
 use dioxus::prelude::*;
-use futures::stream::SplitSink;
+use futures::stream::{self, SplitSink};
 use futures::{SinkExt, StreamExt};
+use gloo_net::eventsource::futures::EventSource;
+use gloo_net::http::Request;
 use gloo_net::websocket;
-use gloo_net::websocket::WebSocketError;
 use gloo_net::websocket::futures::WebSocket;
 use js_sys::wasm_bindgen::JsError;
+use serde_json::Value;
 
 pub use gloo_net::websocket::Message;
 
+/// Header carrying the upstream token of an `/events` stream.
+const TOKEN_HEADER: &str = "x-stream-token";
+
+#[derive(Clone)]
+enum Transport {
+    Socket(Rc<RefCell<SplitSink<WebSocket, Message>>>),
+    /// Server-Sent Events down and `POST` up, both JSON, for when the socket
+    /// does not get through.
+    Stream {
+        url: String,
+        token: Option<String>,
+    },
+}
+
 #[derive(Clone, Copy)]
 pub struct WebSocketHandle {
-    ws_write: Signal<Rc<RefCell<SplitSink<WebSocket, Message>>>>,
+    transport: Signal<Transport>,
     state: Signal<websocket::State>,
     message_bytes: Signal<Vec<u8>>,
 }
 
-/// Opens a web socket connection at the specified `url`.
-pub fn use_web_socket(url: &str) -> Result<WebSocketHandle, JsError> {
+/// `/events` next to the socket at `url`, over HTTP and with the same query.
+fn events_url(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let scheme = match scheme {
+        "ws" => "http",
+        "wss" => "https",
+        _ => return None,
+    };
+    let (path, query) = match rest.split_once('?') {
+        Some((p, q)) => (p, format!("?{}", q)),
+        None => (rest, String::new()),
+    };
+    let (base, _) = path.rsplit_once('/')?;
+    Some(format!("{}://{}/events{}", scheme, base, query))
+}
+
+/// Follows the `/events` stream at `url`, the `session` event it starts with
+/// gives the token to post with.
+async fn follow(url: String, mut transport: Signal<Transport>, mut message_bytes: Signal<Vec<u8>>) {
+    let Ok(mut es) = EventSource::new(&url) else {
+        return;
+    };
+    let (Ok(session), Ok(messages)) = (es.subscribe("session"), es.subscribe("message")) else {
+        return;
+    };
+    let post = url.split('?').next().unwrap_or(&url).to_owned();
+    let mut events = stream::select(session.map(|x| (true, x)), messages.map(|x| (false, x)));
+    // Errors are followed by a reconnect of the browser, and a new session
+    while let Some((first, x)) = events.next().await {
+        let Some(data) = x.ok().and_then(|(_, e)| e.data().as_string()) else {
+            continue;
+        };
+        if first {
+            let token = serde_json::from_str::<Value>(&data)
+                .ok()
+                .and_then(|x| x["token"].as_str().map(str::to_owned));
+            transport.set(Transport::Stream {
+                url: post.clone(),
+                token,
+            });
+        } else {
+            message_bytes.set(data.into_bytes());
+        }
+    }
+    es.close();
+}
+
+/// Opens a web socket connection at the specified `url`, offering `protocols`.
+/// When the socket fails before anything came through, the `/events` stream
+/// is used instead.
+pub fn use_web_socket(url: &str, protocols: &[String]) -> Result<WebSocketHandle, JsError> {
     let state = use_signal(|| websocket::State::Closed);
     let mut message_bytes = use_signal(Vec::new);
+    let fallback = events_url(url);
 
-    let ws = WebSocket::open(url)?;
-    let (write, mut read) = ws.split();
+    let (init, read) = match WebSocket::open_with_protocols(url, protocols) {
+        Ok(ws) => {
+            let (write, read) = ws.split();
+            (Transport::Socket(Rc::new(RefCell::new(write))), Some(read))
+        }
+        Err(e) => match &fallback {
+            Some(events) => (
+                Transport::Stream {
+                    url: events.clone(),
+                    token: None,
+                },
+                None,
+            ),
+            None => return Err(e.into()),
+        },
+    };
+    let transport = use_signal(|| init);
 
     spawn(async move {
-        while let Some(Ok(m)) = read.next().await {
-            match m {
-                Message::Text(t) => message_bytes.set(t.into_bytes()),
-                Message::Bytes(b) => message_bytes.set(b),
+        if let Some(mut read) = read {
+            let mut opened = false;
+            while let Some(Ok(m)) = read.next().await {
+                opened = true;
+                match m {
+                    Message::Text(t) => message_bytes.set(t.into_bytes()),
+                    Message::Bytes(b) => message_bytes.set(b),
+                }
             }
+            if opened {
+                return;
+            }
+        }
+        if let Some(events) = fallback {
+            dioxus::logger::tracing::warn!("websocket failed, falling back to {}", events);
+            follow(events, transport, message_bytes).await;
         }
     });
 
     Ok(WebSocketHandle {
-        ws_write: use_signal(|| Rc::new(RefCell::new(write))),
+        transport,
         state,
         message_bytes,
     })
@@ -45,8 +138,34 @@ pub fn use_web_socket(url: &str) -> Result<WebSocketHandle, JsError> {
 impl WebSocketHandle {
     // TODO: solve this issue
     #[allow(clippy::await_holding_refcell_ref)]
-    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
-        self.ws_write.write().borrow_mut().send(message).await
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        let transport = self.transport.read().clone();
+        match transport {
+            Transport::Socket(w) => Ok(w.borrow_mut().send(message).await?),
+            Transport::Stream { url, token } => {
+                let Message::Text(body) = message else {
+                    bail!("the event stream takes JSON only");
+                };
+                let Some(token) = token else {
+                    bail!("the event stream has not started yet");
+                };
+                let r = Request::post(&url)
+                    .header("content-type", "application/json")
+                    .header(TOKEN_HEADER, &token)
+                    .body(body)?
+                    .send()
+                    .await?;
+                if !r.ok() {
+                    bail!("{} {}", r.status(), r.text().await.unwrap_or_default());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether messages go through the `/events` stream, JSON both ways.
+    pub fn is_stream(self) -> bool {
+        matches!(*self.transport.read(), Transport::Stream { .. })
     }
 
     #[allow(unused)]
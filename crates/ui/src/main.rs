@@ -41,10 +41,7 @@ static STATUS: GlobalSignal<Status> = Global::new(|| {
             token = Some(t);
         };
     };
-    let codec_str = match codec_type {
-        CodecType::Json => "json",
-        CodecType::Cbor => "cbor",
-    };
+    let codec_str = codec_type.name();
     let query = if let Some(token) = token {
         format!("?token={}&codec={}", &token, codec_str)
     } else {